@@ -0,0 +1,10 @@
+CREATE TABLE refresh_tokens (
+   token_hash VARCHAR(64) NOT NULL,
+   family_id VARCHAR(64) NOT NULL,
+   username VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+   expires_at BIGINT NOT NULL,
+   used BOOLEAN NOT NULL DEFAULT false,
+   PRIMARY KEY(token_hash)
+);
+
+CREATE INDEX refresh_tokens_family_id ON refresh_tokens(family_id);
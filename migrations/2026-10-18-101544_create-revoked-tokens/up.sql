@@ -0,0 +1,5 @@
+CREATE TABLE revoked_tokens (
+   jti VARCHAR(64) NOT NULL,
+   expires_at BIGINT NOT NULL,
+   PRIMARY KEY(jti)
+);
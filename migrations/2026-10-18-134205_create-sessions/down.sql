@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN token VARCHAR(255);
+
+DROP TABLE sessions;
@@ -0,0 +1,14 @@
+CREATE TABLE sessions (
+   id VARCHAR(64) NOT NULL,
+   username VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+   token_hash VARCHAR(64) NOT NULL,
+   created_at BIGINT NOT NULL,
+   expires_at BIGINT NOT NULL,
+   user_agent VARCHAR(255),
+   ip_address VARCHAR(45),
+   PRIMARY KEY(id)
+);
+
+CREATE UNIQUE INDEX sessions_token_hash ON sessions(token_hash);
+
+ALTER TABLE users DROP COLUMN token;
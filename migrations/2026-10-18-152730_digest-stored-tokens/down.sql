@@ -0,0 +1,3 @@
+-- Keyed digests cannot be turned back into plain hashes.
+DELETE FROM refresh_tokens;
+DELETE FROM sessions;
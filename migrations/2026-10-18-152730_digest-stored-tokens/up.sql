@@ -0,0 +1,4 @@
+-- Stored tokens are now keyed digests: the former plain hashes can never match
+-- again, so every session has to log in again.
+DELETE FROM refresh_tokens;
+DELETE FROM sessions;
@@ -0,0 +1,13 @@
+CREATE TABLE personal_access_tokens (
+   id VARCHAR(32) NOT NULL,
+   username VARCHAR(255) NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+   name VARCHAR(255) NOT NULL,
+   token_hash VARCHAR(64) NOT NULL,
+   scopes TEXT NOT NULL,
+   created_at BIGINT NOT NULL,
+   expires_at BIGINT,
+   PRIMARY KEY(id)
+);
+
+CREATE UNIQUE INDEX personal_access_tokens_token_hash ON personal_access_tokens(token_hash);
+CREATE INDEX personal_access_tokens_username ON personal_access_tokens(username);
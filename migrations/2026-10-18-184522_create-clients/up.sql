@@ -0,0 +1,7 @@
+CREATE TABLE clients (
+   client_id VARCHAR(64) NOT NULL,
+   secret_hash VARCHAR(64) NOT NULL,
+   scopes TEXT NOT NULL,
+   created_at BIGINT NOT NULL,
+   PRIMARY KEY(client_id)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE used_action_tokens (
+   nonce VARCHAR(64) NOT NULL,
+   expires_at BIGINT NOT NULL,
+   PRIMARY KEY(nonce)
+);
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,3 @@
+DROP INDEX sessions_previous_token_hash;
+ALTER TABLE sessions DROP COLUMN previous_token_expires_at;
+ALTER TABLE sessions DROP COLUMN previous_token_hash;
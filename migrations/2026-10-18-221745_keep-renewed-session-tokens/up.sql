@@ -0,0 +1,5 @@
+-- a renewed token stays valid for a short while, for the requests already sent with it
+ALTER TABLE sessions ADD COLUMN previous_token_hash VARCHAR(64);
+ALTER TABLE sessions ADD COLUMN previous_token_expires_at BIGINT;
+
+CREATE INDEX sessions_previous_token_hash ON sessions(previous_token_hash);
@@ -0,0 +1,2 @@
+-- scopes requested at login, restricting every token of the session
+ALTER TABLE sessions ADD COLUMN scope VARCHAR(255);
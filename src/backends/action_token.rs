@@ -0,0 +1,41 @@
+use crate::{
+    backends::{now, Backend},
+    db::{
+        model::UsedActionToken,
+        schema::used_action_tokens::dsl::{self, used_action_tokens},
+    },
+    error::Error,
+    Result,
+};
+
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+impl Backend {
+    /// Record the use of an action token, failing if it was already used; the entries
+    /// of expired tokens are purged
+    pub fn use_action_token(&self, used_action_token: UsedActionToken) -> Result<()> {
+        self.purge_used_action_tokens()?;
+
+        let mut conn = self.get_connection()?;
+
+        // the nonce is the primary key: of concurrent uses, only one gets it recorded
+        match diesel::insert_into(used_action_tokens)
+            .values(used_action_token)
+            .on_conflict_do_nothing()
+            .execute(&mut conn)?
+        {
+            0 => Err(Error::BadRequest("Token was already used".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop the entries of action tokens that expired anyway
+    pub fn purge_used_action_tokens(&self) -> Result<usize> {
+        let mut conn = self.get_connection()?;
+
+        Ok(
+            diesel::delete(used_action_tokens.filter(dsl::expires_at.lt(now())))
+                .execute(&mut conn)?,
+        )
+    }
+}
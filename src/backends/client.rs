@@ -0,0 +1,67 @@
+use crate::{
+    backends::Backend,
+    db::{
+        model::Client,
+        schema::clients::dsl::{self, clients},
+    },
+    error::Error,
+    Result,
+};
+
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+impl Backend {
+    /// Add a new client
+    pub fn add_client(&self, client: Client) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::insert_into(clients)
+            .values(client)
+            .execute(&mut conn)
+            .map(|_| ())?)
+    }
+
+    /// Search/Find a client with its id & hashed secret
+    pub fn find_client(&self, client_id: &str, secret_hash: &str) -> Result<Client> {
+        let mut conn = self.get_connection()?;
+
+        clients
+            .find(client_id)
+            .filter(dsl::secret_hash.eq(secret_hash))
+            .first(&mut conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => Error::UnauthenticatedUser,
+                _ => e.into(),
+            })
+    }
+
+    /// Get a client by id
+    pub fn get_client(&self, client_id: &str) -> Result<Client> {
+        let mut conn = self.get_connection()?;
+
+        clients
+            .find(client_id)
+            .first(&mut conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => Error::NotFound,
+                _ => e.into(),
+            })
+    }
+
+    /// Retrieve/list all clients
+    pub fn list_clients(&self) -> Result<Vec<Client>> {
+        let mut conn = self.get_connection()?;
+
+        Ok(clients.order(dsl::client_id).load(&mut conn)?)
+    }
+
+    /// Delete a given client
+    pub fn delete_client(&self, client_id: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        match diesel::delete(clients.find(client_id)).execute(&mut conn)? {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
@@ -5,7 +5,20 @@ use crate::Result;
 
 use diesel::r2d2::ConnectionManager;
 use r2d2::{Pool, PooledConnection};
-
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// All action token related methods
+mod action_token;
+/// All OAuth client related methods
+mod client;
+/// All personal access token related methods
+mod personal_access_token;
+/// All refresh token related methods
+mod refresh_token;
+/// All token revocation related methods
+mod revoked_token;
+/// All login session related methods
+mod session;
 /// All user related methods
 mod user;
 
@@ -37,3 +50,11 @@ impl Backend {
         Ok(self.connection_pool.get()?)
     }
 }
+
+/// Current time, in seconds since the epoch
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,148 @@
+use crate::{
+    backends::{now, Backend},
+    db::{
+        model::{PersonalAccessToken, User},
+        schema::{
+            personal_access_tokens::dsl::{self, personal_access_tokens},
+            users,
+        },
+    },
+    error::Error,
+    Result,
+};
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+impl Backend {
+    /// Add a new personal access token
+    pub fn add_personal_access_token(&self, token: PersonalAccessToken) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::insert_into(personal_access_tokens)
+            .values(token)
+            .execute(&mut conn)
+            .map(|_| ())?)
+    }
+
+    /// Search/Find a live personal access token, along with its user, by digest
+    pub fn find_personal_access_token(
+        &self,
+        token_digest: &str,
+    ) -> Result<(PersonalAccessToken, User)> {
+        let mut conn = self.get_connection()?;
+
+        personal_access_tokens
+            .inner_join(users::table)
+            .filter(dsl::token_hash.eq(token_digest))
+            .filter(dsl::expires_at.is_null().or(dsl::expires_at.gt(now())))
+            .first(&mut conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => Error::UnauthenticatedUser,
+                _ => e.into(),
+            })
+    }
+
+    /// Retrieve/list the personal access tokens of a user
+    pub fn list_personal_access_tokens(&self, username: &str) -> Result<Vec<PersonalAccessToken>> {
+        let mut conn = self.get_connection()?;
+
+        Ok(personal_access_tokens
+            .filter(dsl::username.eq(username))
+            .order(dsl::created_at)
+            .load(&mut conn)?)
+    }
+
+    /// Delete a personal access token of a user
+    pub fn delete_personal_access_token(&self, username: &str, id: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        match diesel::delete(
+            personal_access_tokens
+                .find(id)
+                .filter(dsl::username.eq(username)),
+        )
+        .execute(&mut conn)?
+        {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Delete all the personal access tokens of a user
+    pub fn delete_user_personal_access_tokens(&self, username: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        Ok(
+            diesel::delete(personal_access_tokens.filter(dsl::username.eq(username)))
+                .execute(&mut conn)
+                .map(|_| ())?,
+        )
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::testing::{backend, user};
+
+    fn personal_access_token(id: &str, expires_at: Option<i64>) -> PersonalAccessToken {
+        PersonalAccessToken {
+            id: id.to_string(),
+            username: "admin".to_string(),
+            name: id.to_string(),
+            token_hash: format!("{}-hash", id),
+            scopes: "account".to_string(),
+            created_at: now(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn find_the_live_tokens() {
+        let backend = backend();
+        backend.add_user(user(false)).unwrap();
+        for token in [
+            personal_access_token("endless", None),
+            personal_access_token("live", Some(now() + 60)),
+            personal_access_token("expired", Some(now() - 1)),
+        ] {
+            backend.add_personal_access_token(token).unwrap();
+        }
+
+        let (token, user) = backend.find_personal_access_token("endless-hash").unwrap();
+        assert_eq!(token.id, "endless");
+        assert_eq!(token.expires_at, None);
+        assert_eq!(user.username, "admin");
+        assert!(backend.find_personal_access_token("live-hash").is_ok());
+
+        for token_hash in ["expired-hash", "unknown-hash"] {
+            assert!(matches!(
+                backend.find_personal_access_token(token_hash),
+                Err(Error::UnauthenticatedUser)
+            ));
+        }
+        // expired tokens are still listed, for their owner to delete them
+        assert_eq!(
+            backend.list_personal_access_tokens("admin").unwrap().len(),
+            3
+        );
+    }
+
+    #[test]
+    fn delete_the_tokens_of_their_owner_only() {
+        let backend = backend();
+        backend.add_user(user(false)).unwrap();
+        backend
+            .add_personal_access_token(personal_access_token("token", None))
+            .unwrap();
+
+        assert!(matches!(
+            backend.delete_personal_access_token("someone", "token"),
+            Err(Error::NotFound)
+        ));
+        backend
+            .delete_personal_access_token("admin", "token")
+            .unwrap();
+        assert!(backend.find_personal_access_token("token-hash").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{
+    backends::{now, Backend},
+    db::{
+        model::RefreshToken,
+        schema::refresh_tokens::dsl::{self, refresh_tokens},
+    },
+    error::Error,
+    Result,
+};
+
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+
+impl Backend {
+    /// Store a new refresh token, purging the expired ones
+    pub fn add_refresh_token(&self, refresh_token: RefreshToken) -> Result<()> {
+        self.purge_refresh_tokens()?;
+
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::insert_into(refresh_tokens)
+            .values(refresh_token)
+            .execute(&mut conn)
+            .map(|_| ())?)
+    }
+
+    /// Mark a refresh token as used, so that it cannot be used again.
+    ///
+    /// Presenting a token that was already used means it leaked: the whole
+    /// token family, and its login session, is revoked.
+    pub fn use_refresh_token(&self, token_hash: &str) -> Result<RefreshToken> {
+        let mut conn = self.get_connection()?;
+
+        let refresh_token: RefreshToken = refresh_tokens
+            .find(token_hash)
+            .first(&mut conn)
+            .optional()?
+            .ok_or(Error::UnauthenticatedUser)?;
+
+        let updated = diesel::update(refresh_tokens.find(token_hash).filter(dsl::used.eq(false)))
+            .set(dsl::used.eq(true))
+            .execute(&mut conn)?;
+
+        if updated == 0 {
+            self.end_session(&refresh_token.family_id)?;
+            return Err(Error::UnauthenticatedUser);
+        }
+        if refresh_token.expires_at <= now() {
+            return Err(Error::UnauthenticatedUser);
+        }
+
+        Ok(refresh_token)
+    }
+
+    /// Drop the expired refresh tokens; the used ones are kept until then, to detect their reuse
+    pub fn purge_refresh_tokens(&self) -> Result<usize> {
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::delete(refresh_tokens.filter(dsl::expires_at.lt(now()))).execute(&mut conn)?)
+    }
+
+    /// Revoke every refresh token of a family
+    pub fn revoke_refresh_token_family(&self, family_id: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        Ok(
+            diesel::delete(refresh_tokens.filter(dsl::family_id.eq(family_id)))
+                .execute(&mut conn)
+                .map(|_| ())?,
+        )
+    }
+
+    /// Revoke every refresh token of a user
+    pub fn revoke_user_refresh_tokens(&self, username: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        Ok(
+            diesel::delete(refresh_tokens.filter(dsl::username.eq(username)))
+                .execute(&mut conn)
+                .map(|_| ())?,
+        )
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::testing::{backend, session, user};
+
+    /// Backend of `admin`, logged in through the `family` session
+    fn logged_in() -> Backend {
+        let backend = backend();
+        backend.add_user(user(false)).unwrap();
+        backend.add_session(session("family", "access")).unwrap();
+
+        backend
+    }
+
+    fn refresh_token(token_hash: &str, expires_at: i64) -> RefreshToken {
+        RefreshToken {
+            token_hash: token_hash.to_string(),
+            family_id: "family".to_string(),
+            username: "admin".to_string(),
+            expires_at,
+            used: false,
+        }
+    }
+
+    #[test]
+    fn rotate_refresh_tokens() {
+        let backend = logged_in();
+        backend
+            .add_refresh_token(refresh_token("first", now() + 60))
+            .unwrap();
+
+        let used = backend.use_refresh_token("first").unwrap();
+        assert_eq!(used.family_id, "family");
+        assert_eq!(used.username, "admin");
+        backend
+            .add_refresh_token(refresh_token("second", now() + 60))
+            .unwrap();
+
+        assert!(matches!(
+            backend.use_refresh_token("unknown"),
+            Err(Error::UnauthenticatedUser)
+        ));
+        // the session stays alive as long as the tokens are used once
+        assert!(backend.get_session("family").is_ok());
+    }
+
+    #[test]
+    fn revoke_the_family_of_a_reused_token() {
+        let backend = logged_in();
+        backend
+            .add_refresh_token(refresh_token("first", now() + 60))
+            .unwrap();
+        backend.use_refresh_token("first").unwrap();
+        backend
+            .add_refresh_token(refresh_token("second", now() + 60))
+            .unwrap();
+
+        // the rotated token leaked: the whole family and its session are revoked
+        assert!(matches!(
+            backend.use_refresh_token("first"),
+            Err(Error::UnauthenticatedUser)
+        ));
+        assert!(matches!(
+            backend.use_refresh_token("second"),
+            Err(Error::UnauthenticatedUser)
+        ));
+        assert!(matches!(
+            backend.get_session("family"),
+            Err(Error::UnauthenticatedUser)
+        ));
+    }
+
+    #[test]
+    fn purge_the_expired_tokens() {
+        let backend = logged_in();
+        backend
+            .add_refresh_token(refresh_token("used", now() + 60))
+            .unwrap();
+        backend.use_refresh_token("used").unwrap();
+        backend
+            .add_refresh_token(refresh_token("expired", now() - 1))
+            .unwrap();
+
+        assert!(matches!(
+            backend.use_refresh_token("expired"),
+            Err(Error::UnauthenticatedUser)
+        ));
+        assert_eq!(backend.purge_refresh_tokens().unwrap(), 1);
+        assert_eq!(backend.purge_refresh_tokens().unwrap(), 0);
+
+        // the used tokens are kept until they expire, to detect their reuse
+        assert!(backend.use_refresh_token("used").is_err());
+        assert!(backend.get_session("family").is_err());
+
+        // the expired tokens are purged whenever a new one is stored
+        backend
+            .add_refresh_token(refresh_token("expired", now() - 1))
+            .unwrap();
+        backend
+            .add_refresh_token(refresh_token("live", now() + 60))
+            .unwrap();
+        assert_eq!(backend.purge_refresh_tokens().unwrap(), 0);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{
+    backends::{now, Backend},
+    db::{
+        model::RevokedToken,
+        schema::revoked_tokens::dsl::{self, revoked_tokens},
+    },
+    Result,
+};
+
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+impl Backend {
+    /// Revoke a token until it expires, purging the entries of expired tokens
+    pub fn revoke_token(&self, revoked_token: RevokedToken) -> Result<()> {
+        self.purge_revoked_tokens()?;
+
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::insert_into(revoked_tokens)
+            .values(revoked_token)
+            .on_conflict_do_nothing()
+            .execute(&mut conn)
+            .map(|_| ())?)
+    }
+
+    /// Whether the token with the given id was revoked
+    pub fn is_token_revoked(&self, jti: &str) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::select(diesel::dsl::exists(revoked_tokens.find(jti))).get_result(&mut conn)?)
+    }
+
+    /// Drop the entries of tokens that expired anyway
+    pub fn purge_revoked_tokens(&self) -> Result<usize> {
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::delete(revoked_tokens.filter(dsl::expires_at.lt(now()))).execute(&mut conn)?)
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    backends::{now, Backend},
+    db::{
+        model::Session,
+        schema::sessions::dsl::{self, sessions},
+    },
+    error::Error,
+    Result,
+};
+
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+/// Seconds the access token replaced by a renewal stays valid, for the requests already
+/// sent with it
+pub const RENEWAL_OVERLAP: i64 = 60;
+
+impl Backend {
+    /// Start a new login session, purging the expired ones
+    pub fn add_session(&self, session: Session) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        diesel::delete(sessions.filter(dsl::expires_at.lt(now()))).execute(&mut conn)?;
+
+        Ok(diesel::insert_into(sessions)
+            .values(session)
+            .execute(&mut conn)
+            .map(|_| ())?)
+    }
+
+    /// Attach a newly issued access token to a login session
+    pub fn update_session_token(&self, id: &str, token_hash: &str, expires_at: i64) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        match diesel::update(sessions.find(id))
+            .set((
+                dsl::token_hash.eq(token_hash),
+                dsl::expires_at.eq(expires_at),
+                dsl::previous_token_hash.eq(None::<String>),
+                dsl::previous_token_expires_at.eq(None::<i64>),
+            ))
+            .execute(&mut conn)?
+        {
+            0 => Err(Error::UnauthenticatedUser),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get a live login session by id
+    pub fn get_session(&self, id: &str) -> Result<Session> {
+        let mut conn = self.get_connection()?;
+
+        sessions
+            .find(id)
+            .filter(dsl::expires_at.gt(now()))
+            .first(&mut conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => Error::UnauthenticatedUser,
+                _ => e.into(),
+            })
+    }
+
+    /// Swap the current access token of a login session for a renewed one, the replaced
+    /// token staying valid for `RENEWAL_OVERLAP` seconds
+    pub fn renew_session_token(
+        &self,
+        id: &str,
+        current_token_hash: &str,
+        token_hash: &str,
+    ) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        // a replaced token is not renewed again, the renewal already went out
+        match diesel::update(
+            sessions
+                .find(id)
+                .filter(dsl::token_hash.eq(current_token_hash)),
+        )
+        .set((
+            dsl::previous_token_hash.eq(current_token_hash),
+            dsl::previous_token_expires_at.eq(now() + RENEWAL_OVERLAP),
+            dsl::token_hash.eq(token_hash),
+        ))
+        .execute(&mut conn)?
+        {
+            0 => Err(Error::UnauthenticatedUser),
+            _ => Ok(()),
+        }
+    }
+
+    /// End a login session along with its refresh tokens
+    pub fn end_session(&self, id: &str) -> Result<()> {
+        self.revoke_refresh_token_family(id)?;
+
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::delete(sessions.find(id))
+            .execute(&mut conn)
+            .map(|_| ())?)
+    }
+
+    /// End every login session of a user
+    pub fn end_user_sessions(&self, username: &str) -> Result<()> {
+        self.revoke_user_refresh_tokens(username)?;
+
+        let mut conn = self.get_connection()?;
+
+        Ok(diesel::delete(sessions.filter(dsl::username.eq(username)))
+            .execute(&mut conn)
+            .map(|_| ())?)
+    }
+}
@@ -1,36 +1,69 @@
 use crate::{
-    backends::Backend,
+    backends::{now, Backend},
     db::{
         model::{User, UserCredentials},
-        schema::users::dsl::{self, users},
+        schema::{
+            sessions,
+            users::{self as users_table, dsl::users},
+        },
     },
     error::Error,
+    secure::password::PasswordHasher,
     Result,
 };
 
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
 
 impl Backend {
-    /// Search/Find a user with user/pass
-    pub fn find_user(&self, credentials: UserCredentials) -> Result<User> {
+    /// Search/Find a user with user/pass, rehashing its password if it is stored
+    /// as a legacy SHA-256 hash or with another Argon2 cost
+    pub fn find_user(&self, credentials: UserCredentials, hasher: &PasswordHasher) -> Result<User> {
+        let user = match self.get_user(&credentials.username) {
+            Ok(user) => user,
+            Err(Error::NotFound) => {
+                hasher.verify_dummy(&credentials.password);
+                return Err(Error::NotFound);
+            }
+            Err(e) => return Err(e),
+        };
+        if !hasher.verify(&credentials.password, &user.password) {
+            return Err(Error::NotFound);
+        }
+
+        match hasher.needs_rehash(&user.password) {
+            true => self.update_user(User {
+                password: hasher.hash(&credentials.password)?,
+                ..user
+            }),
+            false => Ok(user),
+        }
+    }
+
+    /// Get a user by username
+    pub fn get_user(&self, username: &str) -> Result<User> {
         let mut conn = self.get_connection()?;
 
-        users
-            .find(credentials.username)
-            .filter(dsl::password.eq(credentials.password))
-            .first(&mut conn)
-            .map_err(|e| match e {
-                diesel::result::Error::NotFound => Error::NotFound,
-                _ => e.into(),
-            })
+        users.find(username).first(&mut conn).map_err(|e| match e {
+            diesel::result::Error::NotFound => Error::NotFound,
+            _ => e.into(),
+        })
     }
 
-    /// Search/Find a user by token
-    pub fn find_user_by_token(&self, token: &str) -> Result<User> {
+    /// Search/Find a user by the token digest of one of its live sessions
+    pub fn find_user_by_token(&self, token_digest: &str) -> Result<User> {
         let mut conn = self.get_connection()?;
 
         users
-            .filter(dsl::token.eq(token))
+            .inner_join(sessions::table)
+            .filter(
+                sessions::token_hash
+                    .eq(token_digest)
+                    .or(sessions::previous_token_hash
+                        .eq(token_digest)
+                        .and(sessions::previous_token_expires_at.gt(now()))),
+            )
+            .filter(sessions::expires_at.gt(now()))
+            .select(users_table::all_columns)
             .first(&mut conn)
             .map_err(|e| match e {
                 diesel::result::Error::NotFound => Error::NotFound,
@@ -64,6 +97,9 @@ impl Backend {
 
     /// Delete a given user
     pub fn delete_user(&self, username: &str) -> Result<()> {
+        self.end_user_sessions(username)?;
+        self.delete_user_personal_access_tokens(username)?;
+
         let mut conn = self.get_connection()?;
 
         match diesel::delete(users.find(username)).execute(&mut conn)? {
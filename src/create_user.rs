@@ -1,13 +1,26 @@
 #![allow(unused_must_use)]
 use std::io::Write;
+use clap::Parser;
 use rpassword::read_password;
 use rocketjwt::{
     backends::Backend,
     db::model::User,
-    secure::tokenizer::hash,
+    error::Error,
+    server::password_settings,
 };
 
+#[derive(Parser, Debug)]
+#[clap(author, version, about = "Create an admin user")]
+struct CliOpts {
+    /// loads the password hashing & policy from the server configurations
+    #[clap(short = 'c', long)]
+    config: Option<String>,
+}
+
 fn main() -> rocketjwt::Result<()> {
+    let cli_opts = CliOpts::parse();
+    let (hasher, policy) = password_settings(cli_opts.config.as_deref())?;
+
     let database_url = std::env::var("DATABASE_URL").unwrap_or_default();
     if database_url.is_empty() {
         println!("Database url empty");
@@ -23,6 +36,7 @@ fn main() -> rocketjwt::Result<()> {
 
     print!("Enter the password: ");
     std::io::stdout().flush();
+    // taken as typed, only the line ending stripped, as the server checks it on authentication
     let password = read_password().expect("error: unable to read user input");
 
     print!("Enter the email address: ");
@@ -34,15 +48,24 @@ fn main() -> rocketjwt::Result<()> {
     } else {
         username.trim().to_string()
     };
-    let password = password.trim().to_string();
     let email = email.trim().to_string();
 
+    match policy.check("password", &password, &username, &email) {
+        Err(Error::ValidationError(errors)) => {
+            for error in errors {
+                println!("{}", error.message);
+            }
+            std::process::exit(1);
+        }
+        result => result?,
+    }
+
     backend
         .add_user(User {
             username,
-            password: hash(&password),
+            password: hasher.hash(&password)?,
             is_admin: true,
             email,
-            token: None
+            email_verified: false,
         })
 }
\ No newline at end of file
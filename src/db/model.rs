@@ -1,4 +1,7 @@
-use crate::db::schema::*;
+use crate::{
+    db::schema::*,
+    secure::tokenizer::{ActionPurpose, Actor, TokenClaims},
+};
 use rocket_okapi::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -19,8 +22,9 @@ pub struct User {
     pub email: String,
     pub password: String,
     pub is_admin: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub token: Option<String>,
+    /// Whether the user proved owning the email address, with an email verification token
+    #[serde(default)]
+    pub email_verified: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -28,6 +32,7 @@ pub struct PartialUser {
     pub username: String,
     pub email: String,
     pub is_admin: bool,
+    pub email_verified: bool,
 }
 
 impl From<User> for PartialUser {
@@ -36,6 +41,25 @@ impl From<User> for PartialUser {
             username: user.username,
             email: user.email,
             is_admin: user.is_admin,
+            email_verified: user.email_verified,
+        }
+    }
+}
+
+/// Standard OpenID Connect claims of a user
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UserInfo {
+    pub sub: String,
+    pub email: String,
+    pub email_verified: bool,
+}
+
+impl From<PartialUser> for UserInfo {
+    fn from(user: PartialUser) -> Self {
+        Self {
+            sub: user.username,
+            email: user.email,
+            email_verified: user.email_verified,
         }
     }
 }
@@ -52,7 +76,317 @@ pub struct NewPassword {
     pub new: String,
 }
 
+#[derive(Debug, Clone, Queryable, Identifiable, Insertable)]
+#[diesel(table_name = refresh_tokens, primary_key(token_hash))]
+pub struct RefreshToken {
+    pub token_hash: String,
+    /// All the tokens rotated from the same login share a family
+    pub family_id: String,
+    pub username: String,
+    /// Expiry time, in seconds since the epoch
+    pub expires_at: i64,
+    pub used: bool,
+}
+
+#[derive(Debug, Clone, Queryable, Identifiable, Insertable)]
+#[diesel(table_name = sessions, primary_key(id))]
+pub struct Session {
+    /// Login session id, shared with the refresh token family
+    pub id: String,
+    pub username: String,
+    /// Keyed digest of the current access token of the session
+    pub token_hash: String,
+    /// Creation time, in seconds since the epoch
+    pub created_at: i64,
+    /// Expiry time, in seconds since the epoch
+    pub expires_at: i64,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// Keyed digest of the access token replaced by a renewal
+    pub previous_token_hash: Option<String>,
+    /// Time the replaced access token stops being accepted, in seconds since the epoch
+    pub previous_token_expires_at: Option<i64>,
+    /// Space separated scopes the tokens of the session are restricted to
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Clone, Queryable, Identifiable, Insertable)]
+#[diesel(table_name = personal_access_tokens, primary_key(id))]
+pub struct PersonalAccessToken {
+    pub id: String,
+    pub username: String,
+    pub name: String,
+    /// Keyed digest of the token
+    pub token_hash: String,
+    /// Space separated scopes granted to the token
+    pub scopes: String,
+    /// Creation time, in seconds since the epoch
+    pub created_at: i64,
+    /// Expiry time, in seconds since the epoch; never expires if not set
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PartialPersonalAccessToken {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+impl From<PersonalAccessToken> for PartialPersonalAccessToken {
+    fn from(token: PersonalAccessToken) -> Self {
+        Self {
+            id: token.id,
+            name: token.name,
+            scopes: token.scopes.split_whitespace().map(String::from).collect(),
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NewPersonalAccessToken {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Lifetime of the token (e.g. `90 days`), up to 10 years; never expires if not set
+    #[serde(default)]
+    pub expires_in: Option<String>,
+}
+
+/// A newly created personal access token, the only time the token is shown
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IssuedPersonalAccessToken {
+    #[serde(flatten)]
+    pub info: PartialPersonalAccessToken,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Queryable, Identifiable, Insertable)]
+#[diesel(table_name = clients, primary_key(client_id))]
+pub struct Client {
+    pub client_id: String,
+    pub secret_hash: String,
+    /// Space separated scopes the client may request
+    pub scopes: String,
+    /// Creation time, in seconds since the epoch
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PartialClient {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+}
+
+impl From<Client> for PartialClient {
+    fn from(client: Client) -> Self {
+        Self {
+            client_id: client.client_id,
+            scopes: client.scopes.split_whitespace().map(String::from).collect(),
+            created_at: client.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NewClient {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+/// A newly registered client, the only time its secret is shown
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RegisteredClient {
+    #[serde(flatten)]
+    pub info: PartialClient,
+    pub client_secret: String,
+}
+
+#[derive(Debug, Clone, Queryable, Identifiable, Insertable)]
+#[diesel(table_name = revoked_tokens, primary_key(jti))]
+pub struct RevokedToken {
+    pub jti: String,
+    /// Expiry time of the revoked token, in seconds since the epoch
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone, Queryable, Identifiable, Insertable)]
+#[diesel(table_name = used_action_tokens, primary_key(nonce))]
+pub struct UsedActionToken {
+    pub nonce: String,
+    /// Expiry time of the used action token, in seconds since the epoch
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NewActionToken {
+    pub purpose: ActionPurpose,
+}
+
+/// A single use action token, to be delivered to the user out of band
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ActionToken {
+    pub token: String,
+    pub purpose: ActionPurpose,
+    /// Expiry time, in seconds since the epoch
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PasswordReset {
+    /// Password reset action token
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EmailVerification {
+    /// Email verification action token
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
 #[derive(Serialize, Deserialize, OpenApiFromRequest, JsonSchema)]
 pub struct ApiKey {
+    /// Access token, left out when delivered in the session cookie
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// CSRF token to echo in state-changing requests authenticated by the session cookie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
+    /// Verified claims of the token, set by the request guard
+    #[serde(skip)]
+    pub claims: TokenClaims,
+}
+
+impl ApiKey {
+    /// Only let admin users through, and the OAuth clients registered by them
+    /// (the scopes they were granted restrict them instead)
+    pub fn require_admin(self) -> crate::Result<Self> {
+        if self.claims.is_admin || self.claims.client_id.is_some() {
+            Ok(self)
+        } else {
+            Err(crate::error::Error::ForbiddenAccess)
+        }
+    }
+
+    /// Only let the user themselves through, not an admin impersonating them
+    pub fn require_not_impersonated(self) -> crate::Result<Self> {
+        match self.claims.actor {
+            Some(_) => Err(crate::error::Error::ForbiddenAccess),
+            None => Ok(self),
+        }
+    }
+
+    /// Only let tokens granted the given scope through
+    pub fn require_scope(self, scope: &str) -> crate::Result<Self> {
+        self.require_any_scope(&[scope])
+    }
+
+    /// Only let tokens granted one of the given scopes through
+    pub fn require_any_scope(self, scopes: &[&str]) -> crate::Result<Self> {
+        match &self.claims.scopes {
+            Some(granted)
+                if !granted
+                    .iter()
+                    .any(|granted| scopes.contains(&granted.as_str())) =>
+            {
+                Err(crate::error::Error::ForbiddenAccess)
+            }
+            _ => Ok(self),
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromForm, JsonSchema)]
+pub struct IntrospectionRequest {
     pub token: String,
+    pub token_type_hint: Option<String>,
+}
+
+/// Token request (RFC 6749), the client may authenticate with HTTP Basic instead
+#[derive(Debug, Clone, FromForm, JsonSchema)]
+pub struct TokenRequest {
+    /// `client_credentials`, `password` or `refresh_token`
+    pub grant_type: String,
+    /// Credentials of the user, for the password grant
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Refresh token to rotate, for the refresh token grant
+    pub refresh_token: Option<String>,
+    /// Space separated scopes requested, all the granted ones if not set
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// Successful token response (RFC 6749)
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    /// Lifetime of the access token, in seconds
+    pub expires_in: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// OpenID Connect ID token, when the `openid` scope is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// Token introspection response (RFC 7662)
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct Introspection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Space separated scopes of a personal access token, roles of the user otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// Admin impersonating the user (RFC 8693)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+impl From<TokenClaims> for Introspection {
+    fn from(claims: TokenClaims) -> Self {
+        Self {
+            active: true,
+            // the subject of a client token is the client itself
+            username: Some(claims.subject.clone()).filter(|_| claims.client_id.is_none()),
+            sub: Some(claims.subject),
+            client_id: claims.client_id,
+            scope: Some(claims.scopes.unwrap_or(claims.roles).join(" ")),
+            token_type: Some("Bearer".to_string()),
+            exp: claims.expires_at,
+            iat: Some(claims.issued_at),
+            jti: Some(claims.token_id),
+            act: claims.actor.map(|sub| Actor { sub }),
+        }
+    }
 }
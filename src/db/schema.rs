@@ -1,9 +1,83 @@
+table! {
+    clients (client_id) {
+        client_id -> Text,
+        secret_hash -> Text,
+        scopes -> Text,
+        created_at -> BigInt,
+    }
+}
+
+table! {
+    personal_access_tokens (id) {
+        id -> Text,
+        username -> Text,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Text,
+        created_at -> BigInt,
+        expires_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    refresh_tokens (token_hash) {
+        token_hash -> Text,
+        family_id -> Text,
+        username -> Text,
+        expires_at -> BigInt,
+        used -> Bool,
+    }
+}
+
+table! {
+    revoked_tokens (jti) {
+        jti -> Text,
+        expires_at -> BigInt,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Text,
+        username -> Text,
+        token_hash -> Text,
+        created_at -> BigInt,
+        expires_at -> BigInt,
+        user_agent -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+        previous_token_hash -> Nullable<Text>,
+        previous_token_expires_at -> Nullable<BigInt>,
+        scope -> Nullable<Text>,
+    }
+}
+
+table! {
+    used_action_tokens (nonce) {
+        nonce -> Text,
+        expires_at -> BigInt,
+    }
+}
+
 table! {
     users (username) {
         username -> Text,
         email -> Text,
         password -> Text,
         is_admin -> Bool,
-        token -> Nullable<Text>,
+        email_verified -> Bool,
     }
 }
+
+joinable!(personal_access_tokens -> users (username));
+joinable!(refresh_tokens -> users (username));
+joinable!(sessions -> users (username));
+
+allow_tables_to_appear_in_same_query!(
+    clients,
+    personal_access_tokens,
+    refresh_tokens,
+    revoked_tokens,
+    sessions,
+    used_action_tokens,
+    users,
+);
@@ -21,8 +21,12 @@ pub enum Error {
     RocketError(#[from] Box<rocket::Error>),
     #[error("Invalid access token: {0}")]
     JwtError(#[from] jwt_simple::Error),
+    #[error("Invalid access token: {0}")]
+    PasetoError(String),
     #[error("{0}")]
     BadRequest(String),
+    #[error("Invalid request fields")]
+    ValidationError(Vec<FieldError>),
     #[error("Unauthenticated user")]
     UnauthenticatedUser,
     #[error("User does not have access rights")]
@@ -41,6 +45,22 @@ pub enum Error {
     ConfigFileNotFound,
     #[error("Error getting ssl certificates")]
     SslCertificateError,
+    #[error("Secret key must be at least {0} bytes long")]
+    WeakSecretKey(usize),
+    #[error("server.secret_key is required, generate one with `openssl rand -base64 32`")]
+    MissingSecretKey,
+    #[error(
+        "server.secret_key is a published sample, generate your own with `openssl rand -base64 32`"
+    )]
+    PublishedSecretKey,
+    #[error(
+        "server.jwt_issuer must be server.public_url, the issuer OpenID Connect clients expect"
+    )]
+    IssuerMismatch,
+    #[error("JWT key error: {0}")]
+    JwtKeyError(String),
+    #[error("Password hash error: {0}")]
+    PasswordHashError(String),
     #[error("Empty DB Url")]
     EmptyDBUrl,
     #[error("{0}")]
@@ -53,32 +73,117 @@ pub enum Error {
     Unknown,
 }
 
+/// Reason a field of a request was rejected
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Error of the OAuth token endpoint, in the RFC 6749 format
+#[derive(Error, Debug)]
+pub enum OAuthError {
+    #[error("invalid_request")]
+    InvalidRequest(String),
+    #[error("invalid_client")]
+    InvalidClient,
+    #[error("invalid_grant")]
+    InvalidGrant(String),
+    #[error("unsupported_grant_type")]
+    UnsupportedGrantType(String),
+    #[error("invalid_scope")]
+    InvalidScope(String),
+    #[error("server_error")]
+    ServerError,
+}
+
 impl Error {
     pub fn to_status(&self) -> Status {
         match *self {
             Self::UnauthenticatedUser => Status::Unauthorized,
             Self::ForbiddenAccess => Status::Forbidden,
-            Self::BadRequest(_) | Self::JwtError(_) | Self::InvalidResult(_) => Status::BadRequest,
+            Self::BadRequest(_)
+            | Self::ValidationError(_)
+            | Self::JwtError(_)
+            | Self::PasetoError(_)
+            | Self::InvalidResult(_) => Status::BadRequest,
             Self::NotFound | Self::UnknownRoute => Status::NotFound,
             _ => Status::InternalServerError,
         }
     }
 }
 
+impl OAuthError {
+    pub fn to_status(&self) -> Status {
+        match *self {
+            Self::InvalidClient => Status::Unauthorized,
+            Self::ServerError => Status::InternalServerError,
+            _ => Status::BadRequest,
+        }
+    }
+
+    /// Human readable description of the error (`error_description`)
+    pub fn description(&self) -> Option<&str> {
+        match self {
+            Self::InvalidRequest(description)
+            | Self::InvalidGrant(description)
+            | Self::UnsupportedGrantType(description)
+            | Self::InvalidScope(description) => Some(description),
+            Self::InvalidClient => Some("Client authentication failed"),
+            Self::ServerError => None,
+        }
+    }
+}
+
+impl From<Error> for OAuthError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::BadRequest(description) | Error::FormatError(description) => {
+                OAuthError::InvalidRequest(description)
+            }
+            Error::UnauthenticatedUser => OAuthError::InvalidClient,
+            _ => OAuthError::ServerError,
+        }
+    }
+}
+
+impl From<rocket::form::Errors<'_>> for OAuthError {
+    fn from(e: rocket::form::Errors<'_>) -> Self {
+        OAuthError::InvalidRequest(
+            e.iter()
+                .map(|e| match &e.name {
+                    Some(name) => format!("{}: {}", name, e.kind),
+                    None => e.kind.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+}
+
 impl From<rocket::serde::json::Error<'_>> for Error {
     fn from(e: rocket::serde::json::Error<'_>) -> Self {
         Error::FormatError(format!("{:?}", e))
     }
 }
 
+impl From<rocket::form::Errors<'_>> for Error {
+    fn from(e: rocket::form::Errors<'_>) -> Self {
+        Error::BadRequest(e.to_string())
+    }
+}
+
 impl Serialize for Error {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Error", 2)?;
+        let mut state = serializer.serialize_struct("Error", 3)?;
         state.serialize_field("error", &self.to_string())?;
         state.serialize_field("code", &self.to_status().code)?;
+        if let Self::ValidationError(fields) = self {
+            state.serialize_field("fields", fields)?;
+        }
 
         state.end()
     }
@@ -94,6 +199,44 @@ impl<'r> Responder<'r, 'static> for Error {
     }
 }
 
+impl Serialize for OAuthError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("OAuthError", 2)?;
+        state.serialize_field("error", &self.to_string())?;
+        if let Some(description) = self.description() {
+            state.serialize_field("error_description", description)?;
+        }
+
+        state.end()
+    }
+}
+
+impl<'r> Responder<'r, 'static> for OAuthError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.to_status();
+        let mut response = response::Response::build_from(Json(&self).respond_to(request)?);
+
+        response
+            .status(status)
+            .raw_header("Cache-Control", "no-store")
+            .raw_header("Pragma", "no-cache");
+        if let Self::InvalidClient = self {
+            response.raw_header("WWW-Authenticate", "Basic");
+        }
+
+        response.ok()
+    }
+}
+
+impl OpenApiResponderInner for OAuthError {
+    fn responses(_generator: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        Ok(Responses::default())
+    }
+}
+
 impl OpenApiResponderInner for Error {
     fn responses(_generator: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
         Ok(Responses::default())
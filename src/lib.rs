@@ -18,3 +18,6 @@ pub mod server;
 
 pub mod error;
 pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+pub(crate) mod testing;
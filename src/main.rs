@@ -3,7 +3,7 @@ use rocketjwt::server::init_server;
 use std::process::exit;
 
 #[rocket::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> Result<(), Box<Error>> {
     // start the server
     match init_server().await {
         Ok(server) => server.launch().await,
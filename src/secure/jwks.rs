@@ -0,0 +1,450 @@
+use crate::{
+    db::model::User,
+    error::Error,
+    secure::{
+        keys::VerifyingKey,
+        tokenizer::{roles, TokenClaims},
+    },
+};
+
+use jwt_simple::{
+    prelude::*,
+    reexports::serde_json::{Map, Value},
+};
+use std::{
+    path::PathBuf,
+    sync::RwLock,
+    time::{Instant, SystemTime},
+};
+
+/// Default tolerated clock skew with the trusted issuer
+const LEEWAY: Duration = Duration::from_secs(60);
+/// Default interval between two checks of the JWK Set file
+const RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Public keys of the trusted issuer, along with the modification time of their file
+/// and the last time it was checked
+struct TrustedKeys {
+    modified: Option<SystemTime>,
+    checked: Instant,
+    keys: Vec<VerifyingKey>,
+}
+
+/// Another issuer whose tokens are accepted, verified with the public keys
+/// of the JWK Set file it publishes (reloaded when the file changes)
+pub struct TrustedIssuer {
+    /// Issuer (`iss`) of the tokens, the only one accepted
+    pub issuer: String,
+    /// Audiences (`aud`) of the tokens, at least one of them must be present if any
+    pub audiences: Vec<String>,
+    /// Claim holding the username the tokens are mapped to
+    pub username_claim: String,
+    /// Accept the tokens of users unknown to the database, as ephemeral principals
+    pub ephemeral_principals: bool,
+    /// Grant the admin rights of the user a token is mapped to
+    pub map_admins: bool,
+    /// Tolerated clock skew when checking the token timestamps
+    leeway: Duration,
+    /// Reject tokens issued longer ago than this, whatever their expiry
+    max_token_age: Option<Duration>,
+    /// Minimum interval between two checks of the JWK Set file
+    reload_interval: std::time::Duration,
+    jwks_file: PathBuf,
+    keys: RwLock<TrustedKeys>,
+}
+
+impl TrustedIssuer {
+    /// Trust the issuer of the keys in the given JWK Set file
+    pub fn new(
+        issuer: String,
+        audiences: Vec<String>,
+        username_claim: String,
+        ephemeral_principals: bool,
+        jwks_file: impl Into<PathBuf>,
+    ) -> Result<Self, Error> {
+        let jwks_file = jwks_file.into();
+        let keys = Self::load(&jwks_file)?;
+
+        Ok(Self {
+            issuer,
+            audiences,
+            username_claim,
+            ephemeral_principals,
+            map_admins: false,
+            leeway: LEEWAY,
+            max_token_age: None,
+            reload_interval: RELOAD_INTERVAL,
+            jwks_file,
+            keys: RwLock::new(keys),
+        })
+    }
+
+    /// Set the tolerated clock skew with the issuer
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Reject the tokens of the issuer issued longer ago than the given age
+    pub fn with_max_token_age(mut self, max_token_age: Option<Duration>) -> Self {
+        self.max_token_age = max_token_age;
+        self
+    }
+
+    /// Set the minimum interval between two checks of the JWK Set file
+    pub fn with_reload_interval(mut self, reload_interval: std::time::Duration) -> Self {
+        self.reload_interval = reload_interval;
+        self
+    }
+
+    /// Grant the admin rights of the users the tokens are mapped to
+    pub fn with_admin_mapping(mut self, map_admins: bool) -> Self {
+        self.map_admins = map_admins;
+        self
+    }
+
+    /// Read the keys of the JWK Set file
+    fn load(jwks_file: &PathBuf) -> Result<TrustedKeys, Error> {
+        let modified = std::fs::metadata(jwks_file)?.modified().ok();
+        let keys = VerifyingKey::from_jwks(&std::fs::read_to_string(jwks_file)?)?;
+
+        if keys.is_empty() {
+            return Err(Error::JwtKeyError(format!(
+                "No supported signature key in {}",
+                jwks_file.display()
+            )));
+        }
+
+        Ok(TrustedKeys {
+            modified,
+            checked: Instant::now(),
+            keys,
+        })
+    }
+
+    /// Reload the keys if the JWK Set file changed, checking it once per reload interval
+    /// at most; the former keys are kept as long as the file cannot be loaded
+    fn reload(&self) {
+        match self.keys.read() {
+            Ok(keys) if keys.checked.elapsed() >= self.reload_interval => (),
+            _ => return,
+        }
+
+        if let Ok(mut current) = self.keys.write() {
+            // another request may have checked it meanwhile
+            if current.checked.elapsed() < self.reload_interval {
+                return;
+            }
+            current.checked = Instant::now();
+
+            let modified = std::fs::metadata(&self.jwks_file)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            if modified != current.modified {
+                if let Ok(keys) = Self::load(&self.jwks_file) {
+                    *current = keys;
+                }
+            }
+        }
+    }
+
+    /// Verify a token of the issuer, signed with the key of the given id (any of them if not set)
+    pub fn verify(&self, token: &str, key_id: Option<&str>) -> Result<TokenClaims, Error> {
+        self.reload();
+
+        let options = VerificationOptions {
+            allowed_issuers: Some(HashSet::from([self.issuer.clone()])),
+            allowed_audiences: (!self.audiences.is_empty())
+                .then(|| self.audiences.iter().cloned().collect()),
+            time_tolerance: Some(self.leeway),
+            max_validity: self.max_token_age,
+            ..Default::default()
+        };
+        let keys = self.keys.read().map_err(|_| Error::InternalError)?;
+        let claims = keys
+            .keys
+            .iter()
+            .filter(|key| key_id.is_none_or(|kid| key.key_id() == kid))
+            .find_map(|key| {
+                key.verify::<Map<String, Value>>(token, Some(options.clone()))
+                    .ok()
+            })
+            .ok_or(Error::UnauthenticatedUser)?;
+
+        // map the token to a user with the configured claim
+        let username = match self.username_claim.as_str() {
+            "sub" => claims.subject.clone(),
+            claim => claims
+                .custom
+                .get(claim)
+                .and_then(Value::as_str)
+                .map(String::from),
+        };
+
+        match (username, claims.expires_at) {
+            (Some(username), Some(expires_at)) => Ok(TokenClaims {
+                subject: username,
+                token_id: claims.jwt_id.unwrap_or_default(),
+                issued_at: claims
+                    .issued_at
+                    .map(|iat| iat.as_secs())
+                    .unwrap_or_default(),
+                expires_at: Some(expires_at.as_secs()),
+                external: true,
+                ..Default::default()
+            }),
+            _ => Err(Error::UnauthenticatedUser),
+        }
+    }
+    /// Map the claims of a verified token to the user it names, or to an ephemeral
+    /// principal if the user is unknown and they are accepted
+    pub(crate) fn map_principal(
+        &self,
+        mut claims: TokenClaims,
+        user: Result<User, Error>,
+    ) -> Result<TokenClaims, Error> {
+        match user {
+            // the admin rights of the user are only granted if opted in (`map_admins`)
+            Ok(user) => {
+                claims.is_admin = user.is_admin && self.map_admins;
+                claims.roles = roles(claims.is_admin);
+            }
+            Err(Error::NotFound) if self.ephemeral_principals => {
+                // not granted any scope of this API, which needs a user row
+                claims.is_admin = false;
+                claims.roles = roles(false);
+                claims.scopes = Some(vec![]);
+            }
+            Err(_) => return Err(Error::UnauthenticatedUser),
+        }
+
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        secure::keys::{Algorithm, Jwks, SigningKey},
+        testing::user,
+    };
+    use jwt_simple::reexports::serde_json;
+    use std::{fs::File, path::Path};
+
+    const ISSUER: &str = "https://idp.example.com";
+
+    /// A fresh ES256 key of the trusted issuer
+    fn signing_key() -> SigningKey {
+        SigningKey::from_pem(
+            Algorithm::ES256,
+            &ES256KeyPair::generate().to_pem().unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// Publish the public keys of the given keys in a JWK Set file
+    fn write_jwks(jwks_file: &Path, keys: &[&SigningKey]) {
+        let jwks = Jwks {
+            keys: keys
+                .iter()
+                .filter_map(|key| key.verifying_key().to_jwk())
+                .collect(),
+        };
+        std::fs::write(jwks_file, serde_json::to_string(&jwks).unwrap()).unwrap();
+    }
+
+    /// JWK Set file dedicated to a test
+    fn jwks_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rocketjwt-{}-{name}.json", std::process::id()))
+    }
+
+    fn trusted_issuer(jwks_file: &Path) -> TrustedIssuer {
+        TrustedIssuer::new(
+            ISSUER.to_string(),
+            vec!["api".to_string()],
+            "sub".to_string(),
+            false,
+            jwks_file,
+        )
+        .unwrap()
+    }
+
+    fn claims() -> JWTClaims<Map<String, Value>> {
+        let mut custom = Map::new();
+        custom.insert("preferred_username".into(), "admin".into());
+
+        Claims::with_custom_claims(custom, Duration::from_mins(5))
+            .with_issuer(ISSUER)
+            .with_audience("api")
+            .with_subject("0b1d6f2e")
+    }
+
+    #[test]
+    fn load_the_jwks_file() {
+        let path = jwks_file("load");
+        assert!(TrustedIssuer::new(ISSUER.into(), vec![], "sub".into(), false, &path).is_err());
+
+        std::fs::write(&path, "not a JWK Set").unwrap();
+        assert!(TrustedIssuer::new(ISSUER.into(), vec![], "sub".into(), false, &path).is_err());
+
+        // no signature key
+        write_jwks(
+            &path,
+            &[&SigningKey::from_secret(crate::testing::SECRET_KEY).unwrap()],
+        );
+        assert!(TrustedIssuer::new(ISSUER.into(), vec![], "sub".into(), false, &path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn verify_tokens_of_the_issuer() {
+        let path = jwks_file("verify");
+        let (key, other_key) = (signing_key(), signing_key());
+        write_jwks(&path, &[&key, &other_key]);
+        let trusted_issuer = trusted_issuer(&path);
+
+        let token = key.sign(claims()).unwrap();
+        let claims = trusted_issuer.verify(&token, None).unwrap();
+        assert_eq!(claims.subject, "0b1d6f2e");
+        assert!(claims.external);
+        assert!(!claims.is_admin);
+
+        // the key of the given id only is tried
+        let kid = key.verifying_key().key_id();
+        assert!(trusted_issuer.verify(&token, Some(&kid)).is_ok());
+        let other_kid = other_key.verifying_key().key_id();
+        assert!(trusted_issuer.verify(&token, Some(&other_kid)).is_err());
+        assert!(trusted_issuer.verify(&token, Some("unknown")).is_err());
+
+        // tokens are mapped to the user of the configured claim
+        let by_username = TrustedIssuer {
+            username_claim: "preferred_username".to_string(),
+            ..self::trusted_issuer(&path)
+        };
+        assert_eq!(by_username.verify(&token, None).unwrap().subject, "admin");
+        let by_email = TrustedIssuer {
+            username_claim: "email".to_string(),
+            ..self::trusted_issuer(&path)
+        };
+        assert!(by_email.verify(&token, None).is_err());
+
+        for claims in [
+            self::claims().with_issuer("https://other.example.com"),
+            self::claims().with_audience("other"),
+            Claims::with_custom_claims(Map::new(), Duration::from_mins(5))
+                .with_issuer(ISSUER)
+                .with_audience("api"),
+        ] {
+            let token = key.sign(claims).unwrap();
+            assert!(trusted_issuer.verify(&token, None).is_err());
+        }
+
+        // a token that never expires is rejected
+        let mut claims = self::claims();
+        claims.expires_at = None;
+        let token = key.sign(claims).unwrap();
+        assert!(trusted_issuer.verify(&token, None).is_err());
+
+        // and so is one of another issuer
+        let token = signing_key().sign(self::claims()).unwrap();
+        assert!(trusted_issuer.verify(&token, None).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reload_the_jwks_file_once_per_interval() {
+        let path = jwks_file("reload");
+        let (key, rotated_key) = (signing_key(), signing_key());
+        write_jwks(&path, &[&key]);
+        let trusted_issuer = trusted_issuer(&path);
+        let token = key.sign(claims()).unwrap();
+        let rotated_token = rotated_key.sign(claims()).unwrap();
+
+        // the modification time is set, for the change to be seen within the same tick
+        let rotate = |keys: &[&SigningKey], modified: SystemTime| {
+            write_jwks(&path, keys);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        rotate(&[&rotated_key], SystemTime::UNIX_EPOCH);
+
+        // not checked again before the reload interval
+        assert!(trusted_issuer.verify(&token, None).is_ok());
+        assert!(trusted_issuer.verify(&rotated_token, None).is_err());
+
+        let trusted_issuer = trusted_issuer.with_reload_interval(std::time::Duration::ZERO);
+        assert!(trusted_issuer.verify(&token, None).is_err());
+        assert!(trusted_issuer.verify(&rotated_token, None).is_ok());
+
+        // the former keys are kept as long as the file cannot be loaded
+        std::fs::write(&path, "not a JWK Set").unwrap();
+        assert!(trusted_issuer.verify(&rotated_token, None).is_ok());
+
+        rotate(&[&key, &rotated_key], SystemTime::now());
+        assert!(trusted_issuer.verify(&token, None).is_ok());
+        assert!(trusted_issuer.verify(&rotated_token, None).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn map_the_principals() {
+        let path = jwks_file("principals");
+        write_jwks(&path, &[&signing_key()]);
+        let claims = || TokenClaims {
+            subject: "admin".to_string(),
+            external: true,
+            ..Default::default()
+        };
+
+        // the admin rights of a user are not granted by default
+        let trusted_issuer = trusted_issuer(&path);
+        let mapped = trusted_issuer
+            .map_principal(claims(), Ok(user(true)))
+            .unwrap();
+        assert!(!mapped.is_admin);
+        assert_eq!(mapped.roles, roles(false));
+        assert!(mapped.scopes.is_none());
+
+        let trusted_issuer = trusted_issuer.with_admin_mapping(true);
+        let mapped = trusted_issuer
+            .map_principal(claims(), Ok(user(true)))
+            .unwrap();
+        assert!(mapped.is_admin);
+        assert_eq!(mapped.roles, roles(true));
+        let mapped = trusted_issuer
+            .map_principal(claims(), Ok(user(false)))
+            .unwrap();
+        assert!(!mapped.is_admin);
+
+        // the unknown users are rejected unless accepted as ephemeral principals
+        assert!(matches!(
+            trusted_issuer.map_principal(claims(), Err(Error::NotFound)),
+            Err(Error::UnauthenticatedUser)
+        ));
+        let trusted_issuer = TrustedIssuer {
+            ephemeral_principals: true,
+            ..trusted_issuer
+        };
+        let mapped = trusted_issuer
+            .map_principal(claims(), Err(Error::NotFound))
+            .unwrap();
+        assert!(!mapped.is_admin);
+        assert_eq!(mapped.roles, roles(false));
+        assert_eq!(mapped.scopes, Some(vec![]));
+
+        // whatever the database failure
+        assert!(trusted_issuer
+            .map_principal(claims(), Err(Error::InternalError))
+            .is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,736 @@
+use crate::error::Error;
+
+use hkdf::Hkdf;
+use jwt_simple::{
+    prelude::*,
+    reexports::{
+        ct_codecs::{Base64UrlSafeNoPadding, Decoder, Hex},
+        serde_json,
+    },
+};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+
+/// Minimum length, in bytes, of the decoded HS256 secret
+pub(crate) const MIN_SECRET_KEY_LEN: usize = 32;
+
+/// Supported JWT signing algorithms
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// HMAC with SHA-256, using the server secret key
+    #[default]
+    HS256,
+    /// RSASSA-PKCS1-v1_5 with SHA-256
+    RS256,
+    /// ECDSA using P-256 and SHA-256
+    ES256,
+    /// Ed25519 signatures
+    EdDSA,
+}
+
+impl Algorithm {
+    /// Whether the tokens can be verified with a public key, by parties unable to sign them
+    pub fn is_asymmetric(&self) -> bool {
+        !matches!(self, Self::HS256)
+    }
+}
+
+/// Supported JWE key management algorithms, the content is always encrypted with A256GCM
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encryption {
+    /// Signed only (JWS), the claims are readable by the token holder
+    #[default]
+    None,
+    /// AES-256 key wrap, with a 32 bytes secret key
+    A256KW,
+    /// ECDH-ES key agreement on P-256 then AES-256 key wrap, with a P-256 private key
+    #[serde(rename = "ECDH-ES+A256KW")]
+    EcdhEsA256KW,
+}
+
+/// Key material used to sign tokens
+pub enum SigningKey {
+    HS256(HS256Key),
+    RS256(RS256KeyPair),
+    ES256(ES256KeyPair),
+    EdDSA(Ed25519KeyPair),
+}
+
+/// Key material used to verify tokens
+#[derive(Clone)]
+pub enum VerifyingKey {
+    HS256(HS256Key),
+    RS256(RS256PublicKey),
+    ES256(ES256PublicKey),
+    EdDSA(Ed25519PublicKey),
+}
+
+/// Key material used to encrypt and decrypt tokens (JWE).
+///
+/// Encrypted tokens are nested: they carry a token signed beforehand, as anyone holding
+/// the public half of an ECDH-ES key could encrypt claims of their own.
+pub enum EncryptionKey {
+    A256KW(A256KWKey),
+    EcdhEsA256KW(EcdhEsA256KWDecryptionKey),
+}
+
+/// Claims of an encrypted token: the signed token it carries
+#[derive(Serialize, Deserialize)]
+struct NestedToken {
+    jwt: String,
+}
+
+/// Public half of a verification key, as published in a JWK Set (RFC 7517)
+#[derive(Serialize, Clone, Debug)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub kid: String,
+    pub alg: Algorithm,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+/// A JWK Set
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// Public key of a JWK Set published by another issuer
+#[derive(Deserialize, Debug)]
+struct PublishedJwk {
+    kty: String,
+    kid: Option<String>,
+    alg: Option<String>,
+    #[serde(rename = "use")]
+    key_use: Option<String>,
+    crv: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+/// A JWK Set published by another issuer, its keys are parsed one by one
+#[derive(Deserialize, Debug)]
+struct PublishedJwks {
+    keys: Vec<serde_json::Value>,
+}
+
+/// Base64url (no padding) encoding used by JWK members
+fn b64url(data: &[u8]) -> String {
+    Base64UrlSafeNoPadding::encode_to_string(data).unwrap_or_default()
+}
+
+/// Decode a secret as hex or base64, falling back to its raw bytes
+pub(crate) fn decode_secret_key(secret_key: &str) -> Vec<u8> {
+    let secret_key = secret_key.trim();
+
+    Hex::decode_to_vec(secret_key, None)
+        .or_else(|_| Base64::decode_to_vec(secret_key, None))
+        .unwrap_or_else(|_| secret_key.as_bytes().to_vec())
+}
+
+/// Derive a key of `len` bytes dedicated to the use named by `label` from a secret (HKDF-SHA256),
+/// so that the same secret never keys two different algorithms
+pub(crate) fn derive_key(secret: &[u8], label: &str, len: usize) -> Result<Vec<u8>, Error> {
+    let mut key = vec![0u8; len];
+    Hkdf::<Sha256>::new(None, secret)
+        .expand(label.as_bytes(), &mut key)
+        .map_err(|e| Error::JwtKeyError(e.to_string()))?;
+
+    Ok(key)
+}
+
+/// Derive a non-secret key id from the (public) key material
+fn key_id(key: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(key))[..16].to_string()
+}
+
+impl SigningKey {
+    /// Build a HS256 key from the server secret key (raw, hex or base64)
+    pub fn from_secret(secret_key: &str) -> Result<Self, Error> {
+        let secret_key = decode_secret_key(secret_key);
+        if secret_key.len() < MIN_SECRET_KEY_LEN {
+            return Err(Error::WeakSecretKey(MIN_SECRET_KEY_LEN));
+        }
+
+        Ok(Self::HS256(
+            HS256Key::from_bytes(&secret_key).with_key_id(&key_id(&secret_key)),
+        ))
+    }
+
+    /// Load an asymmetric private key from its PEM encoding
+    pub fn from_pem(algorithm: Algorithm, pem: &str) -> Result<Self, Error> {
+        let key = match algorithm {
+            Algorithm::HS256 => {
+                return Err(Error::JwtKeyError(
+                    "HS256 uses the server secret key, not a PEM file".to_string(),
+                ))
+            }
+            Algorithm::RS256 => Self::RS256(RS256KeyPair::from_pem(pem)?),
+            Algorithm::ES256 => Self::ES256(ES256KeyPair::from_pem(pem)?),
+            Algorithm::EdDSA => Self::EdDSA(Ed25519KeyPair::from_pem(pem)?),
+        };
+        let kid = key.verifying_key().fingerprint()?;
+
+        Ok(key.with_key_id(&kid))
+    }
+
+    /// The algorithm this key signs with
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::HS256(_) => Algorithm::HS256,
+            Self::RS256(_) => Algorithm::RS256,
+            Self::ES256(_) => Algorithm::ES256,
+            Self::EdDSA(_) => Algorithm::EdDSA,
+        }
+    }
+
+    /// The key able to verify tokens signed by this key, sharing its key id
+    pub fn verifying_key(&self) -> VerifyingKey {
+        match self {
+            Self::HS256(k) => VerifyingKey::HS256(k.clone()),
+            Self::RS256(k) => VerifyingKey::RS256(k.public_key()),
+            Self::ES256(k) => VerifyingKey::ES256(k.public_key()),
+            Self::EdDSA(k) => VerifyingKey::EdDSA(k.public_key()),
+        }
+    }
+
+    fn with_key_id(self, kid: &str) -> Self {
+        match self {
+            Self::HS256(k) => Self::HS256(k.with_key_id(kid)),
+            Self::RS256(k) => Self::RS256(k.with_key_id(kid)),
+            Self::ES256(k) => Self::ES256(k.with_key_id(kid)),
+            Self::EdDSA(k) => Self::EdDSA(k.with_key_id(kid)),
+        }
+    }
+
+    /// Sign the given claims
+    pub fn sign<C: Serialize + DeserializeOwned>(
+        &self,
+        claims: JWTClaims<C>,
+    ) -> Result<String, Error> {
+        Ok(match self {
+            Self::HS256(k) => k.authenticate(claims),
+            Self::RS256(k) => k.sign(claims),
+            Self::ES256(k) => k.sign(claims),
+            Self::EdDSA(k) => k.sign(claims),
+        }?)
+    }
+}
+
+impl VerifyingKey {
+    /// Build a HS256 key from a secret key (raw, hex or base64)
+    pub fn from_secret(secret_key: &str) -> Result<Self, Error> {
+        SigningKey::from_secret(secret_key).map(|key| key.verifying_key())
+    }
+
+    /// Load an asymmetric public key from its PEM encoding
+    pub fn from_pem(algorithm: Algorithm, pem: &str) -> Result<Self, Error> {
+        let key = match algorithm {
+            Algorithm::HS256 => {
+                return Err(Error::JwtKeyError(
+                    "HS256 uses the server secret key, not a PEM file".to_string(),
+                ))
+            }
+            Algorithm::RS256 => Self::RS256(RS256PublicKey::from_pem(pem)?),
+            Algorithm::ES256 => Self::ES256(ES256PublicKey::from_pem(pem)?),
+            Algorithm::EdDSA => Self::EdDSA(Ed25519PublicKey::from_pem(pem)?),
+        };
+        let kid = key.fingerprint()?;
+
+        Ok(key.with_key_id(&kid))
+    }
+
+    /// The algorithm this key verifies
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::HS256(_) => Algorithm::HS256,
+            Self::RS256(_) => Algorithm::RS256,
+            Self::ES256(_) => Algorithm::ES256,
+            Self::EdDSA(_) => Algorithm::EdDSA,
+        }
+    }
+
+    /// Non-secret identifier of this key, as found in the `kid` header
+    pub fn key_id(&self) -> String {
+        match self {
+            Self::HS256(k) => k.key_id(),
+            Self::RS256(k) => k.key_id(),
+            Self::ES256(k) => k.key_id(),
+            Self::EdDSA(k) => k.key_id(),
+        }
+        .clone()
+        .unwrap_or_default()
+    }
+
+    /// Key id derived from the public key material
+    fn fingerprint(&self) -> Result<String, Error> {
+        Ok(match self {
+            Self::HS256(k) => k
+                .key_id()
+                .clone()
+                .ok_or_else(|| Error::JwtKeyError("HS256 key without key id".to_string()))?,
+            Self::RS256(k) => key_id(&k.to_der()?),
+            Self::ES256(k) => key_id(&k.public_key().to_bytes_uncompressed()),
+            Self::EdDSA(k) => key_id(&k.to_bytes()),
+        })
+    }
+
+    fn with_key_id(self, kid: &str) -> Self {
+        match self {
+            Self::HS256(k) => Self::HS256(k.with_key_id(kid)),
+            Self::RS256(k) => Self::RS256(k.with_key_id(kid)),
+            Self::ES256(k) => Self::ES256(k.with_key_id(kid)),
+            Self::EdDSA(k) => Self::EdDSA(k.with_key_id(kid)),
+        }
+    }
+
+    /// Public JWK of this key; `None` for symmetric keys, which must never be published
+    pub fn to_jwk(&self) -> Option<Jwk> {
+        let jwk = |kty| Jwk {
+            kty,
+            kid: self.key_id(),
+            alg: self.algorithm(),
+            key_use: "sig",
+            crv: None,
+            n: None,
+            e: None,
+            x: None,
+            y: None,
+        };
+
+        match self {
+            Self::HS256(_) => None,
+            Self::RS256(k) => {
+                let components = k.to_components();
+                Some(Jwk {
+                    n: Some(b64url(&components.n)),
+                    e: Some(b64url(&components.e)),
+                    ..jwk("RSA")
+                })
+            }
+            Self::ES256(k) => {
+                // SEC1 uncompressed point: 0x04 || x || y
+                let point = k.public_key().to_bytes_uncompressed();
+                Some(Jwk {
+                    crv: Some("P-256"),
+                    x: Some(b64url(&point[1..33])),
+                    y: Some(b64url(&point[33..])),
+                    ..jwk("EC")
+                })
+            }
+            Self::EdDSA(k) => Some(Jwk {
+                crv: Some("Ed25519"),
+                x: Some(b64url(&k.to_bytes())),
+                ..jwk("OKP")
+            }),
+        }
+    }
+
+    /// Load the signature keys of a JWK Set published by another issuer, skipping
+    /// the encryption keys and the ones of unsupported algorithms
+    pub fn from_jwks(jwks: &str) -> Result<Vec<Self>, Error> {
+        let jwks: PublishedJwks =
+            serde_json::from_str(jwks).map_err(|e| Error::JwtKeyError(e.to_string()))?;
+
+        Ok(jwks
+            .keys
+            .into_iter()
+            .filter_map(|jwk| serde_json::from_value::<PublishedJwk>(jwk).ok())
+            .filter(|jwk| jwk.key_use.as_deref() != Some("enc"))
+            .filter_map(|jwk| Self::from_jwk(&jwk).ok())
+            .collect())
+    }
+
+    /// Build a public key from its JWK
+    fn from_jwk(jwk: &PublishedJwk) -> Result<Self, Error> {
+        let decode = |member: &Option<String>| {
+            member
+                .as_deref()
+                .and_then(|member| Base64UrlSafeNoPadding::decode_to_vec(member, None).ok())
+                .ok_or_else(|| Error::JwtKeyError(format!("Invalid {} JWK", jwk.kty)))
+        };
+
+        let key = match (jwk.kty.as_str(), jwk.crv.as_deref(), jwk.alg.as_deref()) {
+            ("RSA", _, None | Some("RS256")) => Self::RS256(RS256PublicKey::from_components(
+                &decode(&jwk.n)?,
+                &decode(&jwk.e)?,
+            )?),
+            ("EC", Some("P-256"), None | Some("ES256")) => {
+                // SEC1 uncompressed point: 0x04 || x || y
+                let point = [vec![4], decode(&jwk.x)?, decode(&jwk.y)?].concat();
+                Self::ES256(ES256PublicKey::from_bytes(&point)?)
+            }
+            ("OKP", Some("Ed25519"), None | Some("EdDSA")) => {
+                Self::EdDSA(Ed25519PublicKey::from_bytes(&decode(&jwk.x)?)?)
+            }
+            (kty, crv, alg) => {
+                return Err(Error::JwtKeyError(format!(
+                    "Unsupported JWK: {} {} {}",
+                    kty,
+                    crv.unwrap_or_default(),
+                    alg.unwrap_or_default()
+                )))
+            }
+        };
+
+        Ok(match &jwk.kid {
+            Some(kid) => key.with_key_id(kid),
+            None => key,
+        })
+    }
+
+    /// Verify a token and return its claims
+    pub fn verify<C: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+        options: Option<VerificationOptions>,
+    ) -> Result<JWTClaims<C>, Error> {
+        Ok(match self {
+            Self::HS256(k) => k.verify_token(token, options),
+            Self::RS256(k) => k.verify_token(token, options),
+            Self::ES256(k) => k.verify_token(token, options),
+            Self::EdDSA(k) => k.verify_token(token, options),
+        }?)
+    }
+}
+
+impl EncryptionKey {
+    /// Build an A256KW key from a 32 bytes secret key (raw, hex or base64)
+    pub fn from_secret(secret_key: &str) -> Result<Self, Error> {
+        let secret_key = decode_secret_key(secret_key);
+        if secret_key.len() != 32 {
+            return Err(Error::JwtKeyError(
+                "A256KW requires a 32 bytes encryption key".to_string(),
+            ));
+        }
+
+        Ok(Self::A256KW(A256KWKey::from_bytes(&secret_key)?))
+    }
+
+    /// Load a P-256 private key from its PEM encoding
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        Ok(Self::EcdhEsA256KW(EcdhEsA256KWDecryptionKey::from_pem(
+            pem,
+        )?))
+    }
+
+    /// Encrypt a signed token, valid for as long as the token it carries
+    pub fn encrypt(&self, signed_token: &str, valid_for: Duration) -> Result<String, Error> {
+        let claims = Claims::with_custom_claims(
+            NestedToken {
+                jwt: signed_token.to_string(),
+            },
+            valid_for,
+        );
+
+        Ok(match self {
+            Self::A256KW(k) => k.encrypt(claims),
+            Self::EcdhEsA256KW(k) => k.encryption_key().encrypt(claims),
+        }?)
+    }
+
+    /// Decrypt a token and return the signed token it carries, still to be verified
+    pub fn decrypt(&self, token: &str, time_tolerance: Duration) -> Result<String, Error> {
+        let options = Some(DecryptionOptions {
+            claim_options: Some(VerificationOptions {
+                time_tolerance: Some(time_tolerance),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let claims: JWTClaims<NestedToken> = match self {
+            Self::A256KW(k) => k.decrypt_token(token, options),
+            Self::EcdhEsA256KW(k) => k.decrypt_token(token, options),
+        }?;
+
+        Ok(claims.custom.jwt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{RSA_PRIVATE_KEY, SECRET_KEY};
+
+    fn claims() -> JWTClaims<NoCustomClaims> {
+        Claims::create(Duration::from_mins(5)).with_subject("admin")
+    }
+
+    #[test]
+    fn decode_secret_keys() {
+        assert_eq!(decode_secret_key("00ff"), vec![0, 255]);
+        assert_eq!(decode_secret_key(" AP8= "), vec![0, 255]);
+        assert_eq!(decode_secret_key("not hex!"), b"not hex!".to_vec());
+    }
+
+    #[test]
+    fn derive_dedicated_keys() {
+        let secret = decode_secret_key(SECRET_KEY);
+
+        let key = derive_key(&secret, "a", 32).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, derive_key(&secret, "a", 32).unwrap());
+        assert_ne!(key, derive_key(&secret, "b", 32).unwrap());
+        assert_ne!(key, secret);
+        assert_eq!(derive_key(&secret, "a", 64).unwrap().len(), 64);
+    }
+
+    #[test]
+    fn reject_weak_secret_keys() {
+        assert!(matches!(
+            SigningKey::from_secret("too short"),
+            Err(Error::WeakSecretKey(MIN_SECRET_KEY_LEN))
+        ));
+        assert!(SigningKey::from_pem(Algorithm::HS256, "").is_err());
+        assert!(VerifyingKey::from_pem(Algorithm::HS256, "").is_err());
+    }
+
+    #[test]
+    fn sign_and_verify_hs256() {
+        let key = SigningKey::from_secret(SECRET_KEY).unwrap();
+        let verifying_key = key.verifying_key();
+        assert_eq!(key.algorithm(), Algorithm::HS256);
+
+        let token = key.sign(claims()).unwrap();
+        let claims = verifying_key
+            .verify::<NoCustomClaims>(&token, None)
+            .unwrap();
+        assert_eq!(claims.subject.as_deref(), Some("admin"));
+
+        let other_key = SigningKey::from_secret(&"ab".repeat(32))
+            .unwrap()
+            .verifying_key();
+        assert_ne!(other_key.key_id(), verifying_key.key_id());
+        assert!(other_key.verify::<NoCustomClaims>(&token, None).is_err());
+    }
+
+    #[test]
+    fn sign_and_verify_with_pem_keys() {
+        let rsa_key = RS256KeyPair::from_pem(RSA_PRIVATE_KEY).unwrap();
+        let es_key = ES256KeyPair::generate();
+        let ed_key = Ed25519KeyPair::generate();
+        let keys = [
+            (
+                Algorithm::RS256,
+                RSA_PRIVATE_KEY.to_string(),
+                rsa_key.public_key().to_pem().unwrap(),
+            ),
+            (
+                Algorithm::ES256,
+                es_key.to_pem().unwrap(),
+                es_key.public_key().to_pem().unwrap(),
+            ),
+            (
+                Algorithm::EdDSA,
+                ed_key.to_pem(),
+                ed_key.public_key().to_pem(),
+            ),
+        ];
+
+        for (algorithm, private_pem, public_pem) in keys {
+            let key = SigningKey::from_pem(algorithm, &private_pem).unwrap();
+            assert_eq!(key.algorithm(), algorithm);
+
+            // the key id only depends on the public key
+            let verifying_key = VerifyingKey::from_pem(algorithm, &public_pem).unwrap();
+            assert_eq!(verifying_key.algorithm(), algorithm);
+            assert_eq!(verifying_key.key_id(), key.verifying_key().key_id());
+
+            let token = key.sign(claims()).unwrap();
+            assert_eq!(
+                Token::decode_metadata(&token).unwrap().key_id(),
+                Some(verifying_key.key_id().as_str())
+            );
+            let claims = verifying_key
+                .verify::<NoCustomClaims>(&token, None)
+                .unwrap();
+            assert_eq!(claims.subject.as_deref(), Some("admin"));
+
+            // the signature covers the claims
+            let segments: Vec<_> = token.split('.').collect();
+            let forged = format!(
+                "{}.{}.{}",
+                segments[0],
+                Base64UrlSafeNoPadding::encode_to_string(r#"{"sub":"root"}"#).unwrap(),
+                segments[2]
+            );
+            assert!(verifying_key
+                .verify::<NoCustomClaims>(&forged, None)
+                .is_err());
+        }
+
+        // nor is a token verified by the public key of another key pair
+        let token = SigningKey::from_pem(Algorithm::ES256, &es_key.to_pem().unwrap())
+            .unwrap()
+            .sign(claims())
+            .unwrap();
+        let other_key = VerifyingKey::from_pem(
+            Algorithm::ES256,
+            &ES256KeyPair::generate().public_key().to_pem().unwrap(),
+        )
+        .unwrap();
+        assert!(other_key.verify::<NoCustomClaims>(&token, None).is_err());
+        // or of another algorithm
+        assert!(SigningKey::from_pem(Algorithm::EdDSA, RSA_PRIVATE_KEY).is_err());
+    }
+
+    #[test]
+    fn fingerprint_public_keys() {
+        let rsa_key = RS256KeyPair::from_pem(RSA_PRIVATE_KEY).unwrap();
+        let es_key = ES256KeyPair::generate();
+        let ed_key = Ed25519KeyPair::generate();
+        let fingerprints = [
+            (
+                VerifyingKey::RS256(rsa_key.public_key()),
+                key_id(&rsa_key.public_key().to_der().unwrap()),
+            ),
+            (
+                VerifyingKey::ES256(es_key.public_key()),
+                key_id(&es_key.public_key().public_key().to_bytes_uncompressed()),
+            ),
+            (
+                VerifyingKey::EdDSA(ed_key.public_key()),
+                key_id(&ed_key.public_key().to_bytes()),
+            ),
+        ];
+
+        for (key, kid) in fingerprints {
+            assert_eq!(key.fingerprint().unwrap(), kid);
+            assert_eq!(kid.len(), 16);
+        }
+        assert_eq!(
+            SigningKey::from_pem(Algorithm::RS256, RSA_PRIVATE_KEY)
+                .unwrap()
+                .verifying_key()
+                .key_id(),
+            key_id(&rsa_key.public_key().to_der().unwrap())
+        );
+
+        // no key id is made up for a key that has none
+        assert!(matches!(
+            VerifyingKey::HS256(HS256Key::generate()).fingerprint(),
+            Err(Error::JwtKeyError(_))
+        ));
+    }
+
+    #[test]
+    fn publish_public_keys_only() {
+        let hs_key = SigningKey::from_secret(SECRET_KEY).unwrap();
+        assert!(hs_key.verifying_key().to_jwk().is_none());
+
+        let rsa_key = SigningKey::from_pem(Algorithm::RS256, RSA_PRIVATE_KEY).unwrap();
+        let jwk = rsa_key.verifying_key().to_jwk().unwrap();
+        assert_eq!((jwk.kty, jwk.key_use), ("RSA", "sig"));
+        assert_eq!(jwk.kid, rsa_key.verifying_key().key_id());
+        assert_eq!(jwk.e.as_deref(), Some("AQAB"));
+        // 2048 bits modulus
+        assert_eq!(jwk.n.unwrap().len(), 342);
+
+        let es_key = ES256KeyPair::generate();
+        let jwk = SigningKey::from_pem(Algorithm::ES256, &es_key.to_pem().unwrap())
+            .unwrap()
+            .verifying_key()
+            .to_jwk()
+            .unwrap();
+        assert_eq!((jwk.kty, jwk.crv), ("EC", Some("P-256")));
+        let point = es_key.public_key().public_key().to_bytes_uncompressed();
+        assert_eq!(jwk.x.unwrap(), b64url(&point[1..33]));
+        assert_eq!(jwk.y.unwrap(), b64url(&point[33..]));
+
+        let ed_key = Ed25519KeyPair::generate();
+        let jwk = SigningKey::from_pem(Algorithm::EdDSA, &ed_key.to_pem())
+            .unwrap()
+            .verifying_key()
+            .to_jwk()
+            .unwrap();
+        assert_eq!((jwk.kty, jwk.crv), ("OKP", Some("Ed25519")));
+        assert_eq!(jwk.x, Some(b64url(&ed_key.public_key().to_bytes())));
+        assert!(jwk.y.is_none());
+
+        let jwk = jwt_simple::reexports::serde_json::to_value(&jwk).unwrap();
+        assert_eq!(jwk["alg"], "EdDSA");
+        assert_eq!(jwk["use"], "sig");
+    }
+
+    #[test]
+    fn load_published_jwks() {
+        let keys = [
+            SigningKey::from_pem(Algorithm::RS256, RSA_PRIVATE_KEY).unwrap(),
+            SigningKey::from_pem(
+                Algorithm::ES256,
+                &ES256KeyPair::generate().to_pem().unwrap(),
+            )
+            .unwrap(),
+            SigningKey::from_pem(Algorithm::EdDSA, &Ed25519KeyPair::generate().to_pem()).unwrap(),
+        ];
+        let jwks = Jwks {
+            keys: keys
+                .iter()
+                .filter_map(|key| key.verifying_key().to_jwk())
+                .collect(),
+        };
+        let mut jwks = serde_json::to_value(&jwks).unwrap();
+        // the encryption keys and the ones of unsupported algorithms are skipped
+        jwks["keys"].as_array_mut().unwrap().extend([
+            serde_json::json!({"kty": "RSA", "use": "enc", "n": "AQAB", "e": "AQAB"}),
+            serde_json::json!({"kty": "EC", "crv": "P-521", "x": "AA", "y": "AA"}),
+            serde_json::json!({"kty": "oct", "k": "AA"}),
+        ]);
+
+        let verifying_keys = VerifyingKey::from_jwks(&jwks.to_string()).unwrap();
+        assert_eq!(verifying_keys.len(), 3);
+        for (signing_key, verifying_key) in keys.iter().zip(&verifying_keys) {
+            assert_eq!(verifying_key.key_id(), signing_key.verifying_key().key_id());
+            let token = signing_key.sign(claims()).unwrap();
+            assert!(verifying_key.verify::<NoCustomClaims>(&token, None).is_ok());
+        }
+
+        assert!(VerifyingKey::from_jwks("not a JWK Set").is_err());
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_nested_tokens() {
+        assert!(EncryptionKey::from_secret("too short").is_err());
+
+        let signed_token = SigningKey::from_secret(SECRET_KEY)
+            .unwrap()
+            .sign(claims())
+            .unwrap();
+        let keys = [
+            EncryptionKey::from_secret(&"ab".repeat(32)).unwrap(),
+            EncryptionKey::from_pem(&EcdhEsA256KWDecryptionKey::generate().to_pem().unwrap())
+                .unwrap(),
+        ];
+
+        for key in &keys {
+            let token = key.encrypt(&signed_token, Duration::from_mins(5)).unwrap();
+            assert_eq!(token.split('.').count(), 5);
+            assert_eq!(
+                key.decrypt(&token, Duration::from_secs(0)).unwrap(),
+                signed_token
+            );
+        }
+
+        // a token encrypted for another key cannot be decrypted
+        let token = keys[0]
+            .encrypt(&signed_token, Duration::from_mins(5))
+            .unwrap();
+        let other_key = EncryptionKey::from_secret(&"cd".repeat(32)).unwrap();
+        assert!(other_key.decrypt(&token, Duration::from_secs(0)).is_err());
+        assert!(keys[1].decrypt(&token, Duration::from_secs(0)).is_err());
+    }
+}
@@ -1,2 +1,7 @@
 pub(crate) mod cert;
+pub mod jwks;
+pub mod keys;
+pub mod paseto;
+pub mod password;
+pub mod password_policy;
 pub mod tokenizer;
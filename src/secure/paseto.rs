@@ -0,0 +1,483 @@
+use crate::{
+    error::Error,
+    secure::{
+        keys::decode_secret_key,
+        tokenizer::{TokenValidation, UserClaims},
+    },
+};
+
+use blake2b_simd::Params;
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher},
+    XChaCha20,
+};
+use jwt_simple::{
+    claims::Audiences,
+    prelude::*,
+    reexports::{
+        rand::{thread_rng, RngCore},
+        serde_json,
+    },
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOCAL_HEADER: &str = "v4.local.";
+const PUBLIC_HEADER: &str = "v4.public.";
+const NONCE_LEN: usize = 32;
+const MAC_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// Key material of PASETO v4 tokens (<https://github.com/paseto-standard/paseto-spec>)
+pub enum PasetoKey {
+    /// Symmetric key of `v4.local` tokens
+    Local([u8; 32]),
+    /// Ed25519 key pair of `v4.public` tokens
+    Public(ed25519_compact::KeyPair),
+}
+
+/// Registered PASETO claims, timestamps being RFC 3339 date times
+#[derive(Serialize, Deserialize)]
+struct PasetoClaims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jti: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iat: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nbf: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exp: Option<String>,
+    #[serde(flatten)]
+    custom: UserClaims,
+}
+
+/// Pre-authentication encoding of the pieces of a token
+fn pae(pieces: &[&[u8]]) -> Vec<u8> {
+    let mut encoded = (pieces.len() as u64).to_le_bytes().to_vec();
+    for piece in pieces {
+        encoded.extend_from_slice(&(piece.len() as u64).to_le_bytes());
+        encoded.extend_from_slice(piece);
+    }
+
+    encoded
+}
+
+/// Keyed BLAKE2b of the given length
+fn blake2b(key: &[u8], len: usize, data: &[&[u8]]) -> blake2b_simd::Hash {
+    let mut state = Params::new().hash_length(len).key(key).to_state();
+    for data in data {
+        state.update(data);
+    }
+
+    state.finalize()
+}
+
+fn b64url(data: &[u8]) -> String {
+    Base64UrlSafeNoPadding::encode_to_string(data).unwrap_or_default()
+}
+
+fn invalid_token(reason: &str) -> Error {
+    Error::PasetoError(reason.to_string())
+}
+
+fn to_timestamp(time: Option<UnixTimeStamp>) -> Option<String> {
+    time.map(|time| {
+        humantime::format_rfc3339_seconds(
+            UNIX_EPOCH + std::time::Duration::from_secs(time.as_secs()),
+        )
+        .to_string()
+    })
+}
+
+fn from_timestamp(time: Option<String>) -> Result<Option<UnixTimeStamp>, Error> {
+    time.map(|time| {
+        humantime::parse_rfc3339_weak(&time)
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| UnixTimeStamp::from_secs(time.as_secs()))
+            .ok_or_else(|| invalid_token("invalid date time claim"))
+    })
+    .transpose()
+}
+
+impl PasetoKey {
+    /// Build a `v4.local` key from a 32 bytes secret key (raw, hex or base64)
+    pub fn from_secret(secret_key: &str) -> Result<Self, Error> {
+        decode_secret_key(secret_key)
+            .try_into()
+            .map(Self::Local)
+            .map_err(|_| Error::JwtKeyError("v4.local requires a 32 bytes key".to_string()))
+    }
+
+    /// Load a `v4.public` Ed25519 private key from its PEM encoding
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        ed25519_compact::KeyPair::from_pem(pem)
+            .map(Self::Public)
+            .map_err(|e| Error::JwtKeyError(e.to_string()))
+    }
+
+    /// Header of the tokens of this key
+    pub fn header(&self) -> &'static str {
+        match self {
+            Self::Local(_) => LOCAL_HEADER,
+            Self::Public(_) => PUBLIC_HEADER,
+        }
+    }
+
+    /// Seal the given claims into a token
+    pub fn seal(&self, claims: JWTClaims<UserClaims>) -> Result<String, Error> {
+        // `aud` is a single string in PASETO, there is no picking one of several
+        let aud = match claims.audiences.map(Audiences::into_set) {
+            Some(audiences) if audiences.len() > 1 => {
+                return Err(Error::JwtKeyError(
+                    "PASETO tokens carry a single audience".to_string(),
+                ))
+            }
+            audiences => audiences.and_then(|audiences| audiences.into_iter().next()),
+        };
+        let message = serde_json::to_vec(&PasetoClaims {
+            iss: claims.issuer,
+            sub: claims.subject,
+            aud,
+            jti: claims.jwt_id,
+            iat: to_timestamp(claims.issued_at),
+            nbf: to_timestamp(claims.invalid_before),
+            exp: to_timestamp(claims.expires_at),
+            custom: claims.custom,
+        })
+        .map_err(|e| Error::FormatError(e.to_string()))?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+
+        Ok(self.seal_message(message, &nonce))
+    }
+
+    /// Seal a message into a token, encrypted with the given nonce for `v4.local`
+    fn seal_message(&self, message: Vec<u8>, nonce: &[u8; NONCE_LEN]) -> String {
+        let body = match self {
+            Self::Local(key) => {
+                let (mut ciphertext, auth_key) = Self::encrypt(key, nonce, message);
+                let mac = blake2b(
+                    auth_key.as_bytes(),
+                    MAC_LEN,
+                    &[&pae(&[
+                        LOCAL_HEADER.as_bytes(),
+                        nonce,
+                        &ciphertext,
+                        b"",
+                        b"",
+                    ])],
+                );
+
+                let mut body = nonce.to_vec();
+                body.append(&mut ciphertext);
+                body.extend_from_slice(mac.as_bytes());
+                body
+            }
+            Self::Public(key_pair) => {
+                let signature = key_pair
+                    .sk
+                    .sign(pae(&[PUBLIC_HEADER.as_bytes(), &message, b"", b""]), None);
+
+                let mut body = message;
+                body.extend_from_slice(signature.as_ref());
+                body
+            }
+        };
+
+        format!("{}{}", self.header(), b64url(&body))
+    }
+
+    /// Open a token, check its claims and return them
+    pub fn open(
+        &self,
+        token: &str,
+        validation: &TokenValidation,
+    ) -> Result<JWTClaims<UserClaims>, Error> {
+        let message = self.open_message(token)?;
+
+        let claims: PasetoClaims =
+            serde_json::from_slice(&message).map_err(|_| invalid_token("invalid claims"))?;
+        let claims = JWTClaims {
+            issued_at: from_timestamp(claims.iat)?,
+            expires_at: from_timestamp(claims.exp)?,
+            invalid_before: from_timestamp(claims.nbf)?,
+            issuer: claims.iss,
+            subject: claims.sub,
+            audiences: claims.aud.map(Audiences::AsString),
+            jwt_id: claims.jti,
+            nonce: None,
+            custom: claims.custom,
+        };
+        Self::validate(&claims, validation)?;
+
+        Ok(claims)
+    }
+
+    /// Authenticate a token, and decrypt it for `v4.local`, to get its message
+    fn open_message(&self, token: &str) -> Result<Vec<u8>, Error> {
+        // footers are never issued, so never accepted
+        let body = token
+            .strip_prefix(self.header())
+            .filter(|body| !body.contains('.'))
+            .ok_or_else(|| invalid_token("unexpected token header"))?;
+        let body = Base64UrlSafeNoPadding::decode_to_vec(body, None)
+            .map_err(|_| invalid_token("invalid token encoding"))?;
+
+        Ok(match self {
+            Self::Local(key) => {
+                if body.len() < NONCE_LEN + MAC_LEN {
+                    return Err(invalid_token("token too short"));
+                }
+                let (nonce, rest) = body.split_at(NONCE_LEN);
+                let (ciphertext, mac) = rest.split_at(rest.len() - MAC_LEN);
+
+                let (_, auth_key) = Self::derive_keys(key, nonce);
+                // constant time comparison
+                if blake2b(
+                    auth_key.as_bytes(),
+                    MAC_LEN,
+                    &[&pae(&[
+                        LOCAL_HEADER.as_bytes(),
+                        nonce,
+                        ciphertext,
+                        b"",
+                        b"",
+                    ])],
+                ) != *mac
+                {
+                    return Err(invalid_token("invalid token authentication tag"));
+                }
+
+                Self::encrypt(key, nonce, ciphertext.to_vec()).0
+            }
+            Self::Public(key_pair) => {
+                if body.len() < SIGNATURE_LEN {
+                    return Err(invalid_token("token too short"));
+                }
+                let (message, signature) = body.split_at(body.len() - SIGNATURE_LEN);
+
+                key_pair
+                    .pk
+                    .verify(
+                        pae(&[PUBLIC_HEADER.as_bytes(), message, b"", b""]),
+                        &ed25519_compact::Signature::from_slice(signature)
+                            .map_err(|_| invalid_token("invalid token signature"))?,
+                    )
+                    .map_err(|_| invalid_token("invalid token signature"))?;
+
+                message.to_vec()
+            }
+        })
+    }
+
+    /// Encryption and authentication keys of a `v4.local` token
+    fn derive_keys(key: &[u8], nonce: &[u8]) -> (blake2b_simd::Hash, blake2b_simd::Hash) {
+        (
+            blake2b(key, 56, &[b"paseto-encryption-key", nonce]),
+            blake2b(key, 32, &[b"paseto-auth-key-for-aead", nonce]),
+        )
+    }
+
+    /// XChaCha20 of the given data (both ways), along with the authentication key
+    fn encrypt(key: &[u8], nonce: &[u8], mut data: Vec<u8>) -> (Vec<u8>, blake2b_simd::Hash) {
+        let (encryption_key, auth_key) = Self::derive_keys(key, nonce);
+        let (encryption_key, counter_nonce) = encryption_key.as_bytes().split_at(32);
+
+        XChaCha20::new(encryption_key.into(), counter_nonce.into()).apply_keystream(&mut data);
+
+        (data, auth_key)
+    }
+
+    /// Same checks as the ones applied to JWT tokens
+    fn validate(claims: &JWTClaims<UserClaims>, validation: &TokenValidation) -> Result<(), Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+        let leeway = validation.leeway.as_secs();
+
+        if let Some(issued_at) = claims.issued_at.map(|time| time.as_secs()) {
+            if issued_at > now + leeway {
+                return Err(invalid_token("token issued in the future"));
+            }
+            if let Some(max_token_age) = validation.max_token_age {
+                if now.saturating_sub(issued_at) > max_token_age.as_secs() {
+                    return Err(invalid_token("token is too old"));
+                }
+            }
+        }
+        if let Some(invalid_before) = claims.invalid_before.map(|time| time.as_secs()) {
+            if now + leeway < invalid_before {
+                return Err(invalid_token("token not valid yet"));
+            }
+        }
+        match claims.expires_at.map(|time| time.as_secs()) {
+            Some(expires_at) if now.saturating_sub(leeway) <= expires_at => (),
+            _ => return Err(invalid_token("token has expired")),
+        }
+        if claims.issuer.as_deref() != Some(validation.issuer.as_str()) {
+            return Err(invalid_token("issuer mismatch"));
+        }
+        if !validation.audiences.is_empty()
+            && !claims.audiences.as_ref().is_some_and(|audiences| {
+                audiences.contains(&validation.audiences.iter().cloned().collect())
+            })
+        {
+            return Err(invalid_token("audience mismatch"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::validation;
+    use jwt_simple::reexports::ct_codecs::Hex;
+
+    /// Key of the `v4.local` test vectors
+    const LOCAL_KEY: &str = "707172737475767778797a7b7c7d7e7f808182838485868788898a8b8c8d8e8f";
+    /// Secret key (seed and public key) of the `v4.public` test vectors
+    const PUBLIC_SECRET_KEY: &str = "b4cbfb43df4ce210727d953e4a713307fa19bb7d9f85041438d9e11b942a37741eb9dbbbbc047c03fd70604e0071f0987e16b28b757225c11f00415d0e20b1a2";
+
+    fn local_key() -> PasetoKey {
+        PasetoKey::from_secret(LOCAL_KEY).unwrap()
+    }
+
+    fn public_key() -> PasetoKey {
+        let secret_key = Hex::decode_to_vec(PUBLIC_SECRET_KEY, None).unwrap();
+        PasetoKey::Public(ed25519_compact::KeyPair::from_slice(&secret_key).unwrap())
+    }
+
+    /// Validation of the tokens without audience
+    fn any_audience() -> TokenValidation {
+        TokenValidation {
+            audiences: vec![],
+            ..validation()
+        }
+    }
+
+    fn claims() -> JWTClaims<UserClaims> {
+        Claims::with_custom_claims(UserClaims::default(), Duration::from_mins(5))
+            .with_subject("admin")
+            .with_issuer("rocketjwt")
+    }
+
+    // official test vectors: https://github.com/paseto-standard/test-vectors/blob/master/v4.json
+
+    #[test]
+    fn local_test_vectors() {
+        let vectors = [
+            // 4-E-1
+            (
+                r#"{"data":"this is a secret message","exp":"2022-01-01T00:00:00+00:00"}"#,
+                "v4.local.AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQAr68PS4AXe7If_ZgesdkUMvSwscFlAl1pk5HC0e8kApeaqMfGo_7OpBnwJOAbY9V7WU6abu74MmcUE8YWAiaArVI8XJ5hOb_4v9RmDkneN0S92dx0OW4pgy7omxgf3S8c3LlQg",
+            ),
+            // 4-E-2
+            (
+                r#"{"data":"this is a hidden message","exp":"2022-01-01T00:00:00+00:00"}"#,
+                "v4.local.AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQAr68PS4AXe7If_ZgesdkUMvS2csCgglvpk5HC0e8kApeaqMfGo_7OpBnwJOAbY9V7WU6abu74MmcUE8YWAiaArVI8XIemu9chy3WVKvRBfg6t8wwYHK0ArLxxfZP73W_vfwt5A",
+            ),
+        ];
+
+        let key = local_key();
+        for (payload, token) in vectors {
+            assert_eq!(
+                key.seal_message(payload.as_bytes().to_vec(), &[0; NONCE_LEN]),
+                token
+            );
+            assert_eq!(key.open_message(token).unwrap(), payload.as_bytes());
+        }
+    }
+
+    #[test]
+    fn public_test_vectors() {
+        // 4-S-1
+        let payload = r#"{"data":"this is a signed message","exp":"2022-01-01T00:00:00+00:00"}"#;
+        let token = "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9bg_XBBzds8lTZShVlwwKSgeKpLT3yukTw6JUz3W4h_ExsQV-P0V54zemZDcAxFaSeef1QlXEFtkqxT1ciiQEDA";
+
+        let key = public_key();
+        assert_eq!(
+            key.seal_message(payload.as_bytes().to_vec(), &[0; NONCE_LEN]),
+            token
+        );
+        assert_eq!(key.open_message(token).unwrap(), payload.as_bytes());
+    }
+
+    #[test]
+    fn seal_and_open() {
+        for key in [local_key(), public_key()] {
+            let token = key.seal(claims()).unwrap();
+            assert!(token.starts_with(key.header()));
+
+            let claims = key.open(&token, &any_audience()).unwrap();
+            assert_eq!(claims.subject.as_deref(), Some("admin"));
+        }
+    }
+
+    #[test]
+    fn reject_tampered_tokens() {
+        for key in [local_key(), public_key()] {
+            let token = key.seal(claims()).unwrap();
+
+            // flip a bit of the body
+            let mut body =
+                Base64UrlSafeNoPadding::decode_to_vec(&token[key.header().len()..], None).unwrap();
+            body[40] ^= 1;
+            let tampered = format!("{}{}", key.header(), b64url(&body));
+            assert!(key.open(&tampered, &any_audience()).is_err());
+
+            // footers are not accepted
+            assert!(key
+                .open(&format!("{}.Zm9vdGVy", token), &any_audience())
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn reject_tokens_of_another_key_or_purpose() {
+        let token = local_key().seal(claims()).unwrap();
+
+        let other_key = PasetoKey::from_secret(&"ab".repeat(32)).unwrap();
+        assert!(other_key.open(&token, &any_audience()).is_err());
+        assert!(public_key().open(&token, &any_audience()).is_err());
+    }
+
+    #[test]
+    fn check_claims() {
+        let key = local_key();
+
+        let mut expired = claims();
+        expired.expires_at = Some(Clock::now_since_epoch() - Duration::from_mins(1));
+        let expired = key.seal(expired).unwrap();
+        assert!(key.open(&expired, &any_audience()).is_err());
+
+        let not_yet_valid =
+            claims().invalid_before(Clock::now_since_epoch() + Duration::from_mins(1));
+        let not_yet_valid = key.seal(not_yet_valid).unwrap();
+        assert!(key.open(&not_yet_valid, &any_audience()).is_err());
+
+        let other_issuer = key.seal(claims().with_issuer("elsewhere")).unwrap();
+        assert!(key.open(&other_issuer, &any_audience()).is_err());
+
+        let audience = key.seal(claims().with_audience("api")).unwrap();
+        assert!(key.open(&audience, &validation()).is_ok());
+        assert!(key
+            .open(&key.seal(claims()).unwrap(), &validation())
+            .is_err());
+    }
+
+    #[test]
+    fn reject_multiple_audiences() {
+        let claims = claims().with_audiences(HashSet::from(["api", "admin"]));
+        assert!(local_key().seal(claims).is_err());
+    }
+}
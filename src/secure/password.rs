@@ -0,0 +1,155 @@
+use crate::{error::Error, secure::tokenizer::hash};
+
+use argon2::{
+    password_hash::{PasswordHash, SaltString},
+    Algorithm, Argon2, Params, PasswordHasher as _, PasswordVerifier as _, Version,
+};
+use jwt_simple::reexports::rand::{thread_rng, RngCore};
+
+/// Salted Argon2id hashing of the user passwords, in the PHC string format.
+///
+/// Passwords stored by former versions as bare SHA-256 digests are still verified,
+/// and reported as needing a rehash.
+pub struct PasswordHasher {
+    argon2: Argon2<'static>,
+    /// Hash of a random password, verified against when there is no user to verify against,
+    /// so that unknown usernames take as long as wrong passwords
+    dummy_hash: String,
+}
+
+impl PasswordHasher {
+    /// Argon2id hasher with the given memory (in KiB), time and parallelism costs
+    pub fn new(memory_cost: u32, time_cost: u32, parallelism: u32) -> Result<Self, Error> {
+        let params = Params::new(memory_cost, time_cost, parallelism, None)
+            .map_err(|e| Error::PasswordHashError(e.to_string()))?;
+        let mut hasher = Self {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+            dummy_hash: String::new(),
+        };
+        let mut password = [0u8; 32];
+        thread_rng().fill_bytes(&mut password);
+        hasher.dummy_hash = hasher.hash_bytes(&password)?;
+
+        Ok(hasher)
+    }
+
+    /// Hash a password with a random salt
+    pub fn hash(&self, password: &str) -> Result<String, Error> {
+        self.hash_bytes(password.as_bytes())
+    }
+
+    fn hash_bytes(&self, password: &[u8]) -> Result<String, Error> {
+        let mut salt = [0u8; 16];
+        thread_rng().fill_bytes(&mut salt);
+        let salt =
+            SaltString::encode_b64(&salt).map_err(|e| Error::PasswordHashError(e.to_string()))?;
+
+        self.argon2
+            .hash_password(password, &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| Error::PasswordHashError(e.to_string()))
+    }
+
+    /// Whether the password matches the stored hash, an Argon2 or legacy SHA-256 one
+    pub fn verify(&self, password: &str, stored_hash: &str) -> bool {
+        match PasswordHash::new(stored_hash) {
+            Ok(parsed) => self
+                .argon2
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok(),
+            Err(_) => is_legacy_hash(stored_hash) && hash(password) == stored_hash,
+        }
+    }
+
+    /// Spend the time of a verification, when there is no user to verify the password against
+    pub fn verify_dummy(&self, password: &str) {
+        let _ = self.verify(password, &self.dummy_hash);
+    }
+
+    /// Whether the stored hash is a legacy SHA-256 one, or an Argon2 one with other parameters
+    pub fn needs_rehash(&self, stored_hash: &str) -> bool {
+        let parsed = match PasswordHash::new(stored_hash) {
+            Ok(parsed) => parsed,
+            Err(_) => return true,
+        };
+        let params = self.argon2.params();
+
+        parsed.algorithm != Algorithm::Argon2id.ident()
+            || Params::try_from(&parsed).map_or(true, |stored| {
+                stored.m_cost() != params.m_cost()
+                    || stored.t_cost() != params.t_cost()
+                    || stored.p_cost() != params.p_cost()
+            })
+    }
+}
+
+/// Bare SHA-256 hex digests, the format of the passwords stored by former versions
+fn is_legacy_hash(stored_hash: &str) -> bool {
+    stored_hash.len() == 64 && stored_hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hasher() -> PasswordHasher {
+        // low costs, for the tests to run fast
+        PasswordHasher::new(1024, 1, 1).unwrap()
+    }
+
+    #[test]
+    fn hash_and_verify() {
+        let hasher = hasher();
+
+        let stored_hash = hasher.hash("secret").unwrap();
+        assert!(stored_hash.starts_with("$argon2id$"));
+        assert!(hasher.verify("secret", &stored_hash));
+        assert!(!hasher.verify("Secret", &stored_hash));
+        assert!(!hasher.needs_rehash(&stored_hash));
+
+        // salted
+        assert_ne!(stored_hash, hasher.hash("secret").unwrap());
+    }
+
+    #[test]
+    fn verify_legacy_hashes() {
+        let hasher = hasher();
+
+        let stored_hash = hash("secret");
+        assert!(hasher.verify("secret", &stored_hash));
+        assert!(!hasher.verify("Secret", &stored_hash));
+        assert!(hasher.needs_rehash(&stored_hash));
+
+        // neither a legacy nor an Argon2 hash
+        assert!(!hasher.verify("secret", "secret"));
+        assert!(!hasher.verify("", ""));
+    }
+
+    #[test]
+    fn rehash_on_other_parameters() {
+        let stored_hash = hasher().hash("secret").unwrap();
+
+        let hasher = PasswordHasher::new(2048, 1, 1).unwrap();
+        assert!(hasher.verify("secret", &stored_hash));
+        assert!(hasher.needs_rehash(&stored_hash));
+        assert!(PasswordHasher::new(1024, 2, 1)
+            .unwrap()
+            .needs_rehash(&stored_hash));
+        assert!(PasswordHasher::new(1024, 1, 2)
+            .unwrap()
+            .needs_rehash(&stored_hash));
+    }
+
+    #[test]
+    fn reject_invalid_parameters() {
+        assert!(PasswordHasher::new(0, 1, 1).is_err());
+        assert!(PasswordHasher::new(1024, 0, 1).is_err());
+    }
+
+    #[test]
+    fn verify_dummy() {
+        let hasher = hasher();
+        hasher.verify_dummy("secret");
+        assert!(!hasher.verify("secret", &hasher.dummy_hash));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::error::{Error, FieldError};
+
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// Substrings of the username or email address shorter than this are allowed in passwords
+const MIN_USER_INFO_LENGTH: usize = 3;
+
+/// Rules the passwords of the users must follow
+#[derive(Debug)]
+pub struct PasswordPolicy {
+    /// Minimum length, in characters
+    pub min_length: usize,
+    /// Maximum length, in characters
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    /// Require a character that is neither a letter nor a digit
+    pub require_symbol: bool,
+    /// Reject the passwords containing the username or the email address
+    pub reject_user_info: bool,
+    /// Known breached passwords, rejected
+    pub breached_passwords: Option<BreachedPasswords>,
+}
+
+/// Local list of breached passwords
+#[derive(Debug)]
+pub enum BreachedPasswords {
+    /// The passwords themselves, one per line
+    List(HashSet<String>),
+    /// A directory of k-anonymity range files, one per 5 hex digits SHA-1 prefix
+    /// (e.g. `21BD1.txt`), of `SUFFIX:COUNT` lines (the format of Have I Been Pwned)
+    Ranges(PathBuf),
+}
+
+impl BreachedPasswords {
+    /// Load a newline separated list of passwords
+    pub fn from_list(path: &str) -> Result<Self, Error> {
+        Ok(Self::List(
+            fs::read_to_string(path)?
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect(),
+        ))
+    }
+
+    /// Use a directory of k-anonymity range files, read on every check
+    pub fn from_ranges(path: &str) -> Result<Self, Error> {
+        match Path::new(path).is_dir() {
+            true => Ok(Self::Ranges(PathBuf::from(path))),
+            false => Err(Error::Io(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("{} is not a directory", path),
+            ))),
+        }
+    }
+
+    /// Whether the password is a known breached one
+    pub fn contains(&self, password: &str) -> Result<bool, Error> {
+        match self {
+            Self::List(passwords) => Ok(passwords.contains(password)),
+            Self::Ranges(dir) => {
+                let digest = format!("{:X}", Sha1::digest(password.as_bytes()));
+                let (prefix, suffix) = digest.split_at(5);
+                let range = match fs::read_to_string(dir.join(format!("{}.txt", prefix))) {
+                    Ok(range) => range,
+                    // a partial download may lack some of the prefixes
+                    Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+                    Err(e) => return Err(e.into()),
+                };
+
+                Ok(range.lines().any(|line| {
+                    line.split(':')
+                        .next()
+                        .is_some_and(|known| known.trim().eq_ignore_ascii_case(suffix))
+                }))
+            }
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Check the password given in `field` for the given user, reporting every rule it breaks
+    pub fn check(
+        &self,
+        field: &str,
+        password: &str,
+        username: &str,
+        email: &str,
+    ) -> Result<(), Error> {
+        let mut errors = vec![];
+        let mut reject = |message: String| {
+            errors.push(FieldError {
+                field: field.to_string(),
+                message,
+            })
+        };
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            reject(format!(
+                "Password must be at least {} characters long",
+                self.min_length
+            ));
+        }
+        if length > self.max_length {
+            reject(format!(
+                "Password must be at most {} characters long",
+                self.max_length
+            ));
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            reject("Password must contain a lowercase letter".to_string());
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            reject("Password must contain an uppercase letter".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            reject("Password must contain a digit".to_string());
+        }
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            reject("Password must contain a symbol".to_string());
+        }
+        if self.reject_user_info {
+            let password = password.to_lowercase();
+            let local_part = email.split('@').next().unwrap_or_default();
+
+            if [username, email, local_part].iter().any(|info| {
+                info.chars().count() >= MIN_USER_INFO_LENGTH
+                    && password.contains(&info.to_lowercase())
+            }) {
+                reject("Password must not contain the username or email address".to_string());
+            }
+        }
+        if let Some(breached_passwords) = &self.breached_passwords {
+            if breached_passwords.contains(password)? {
+                reject("Password is known to have been breached".to_string());
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(Error::ValidationError(errors)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 16,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            reject_user_info: true,
+            breached_passwords: None,
+        }
+    }
+
+    /// Messages of the rules the password breaks
+    fn check(policy: &PasswordPolicy, password: &str) -> Vec<String> {
+        match policy.check("password", password, "alice", "wonder@example.com") {
+            Ok(()) => vec![],
+            Err(Error::ValidationError(errors)) => {
+                assert!(errors.iter().all(|error| error.field == "password"));
+                errors.into_iter().map(|error| error.message).collect()
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    /// Empty scratch directory of the given test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rocketjwt-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn accept_compliant_passwords() {
+        assert!(check(&policy(), "Tr0ub4dor&3").is_empty());
+        // lengths are counted in characters
+        assert!(check(&policy(), "Pässwörd1!").is_empty());
+    }
+
+    #[test]
+    fn check_lengths() {
+        assert_eq!(
+            check(&policy(), "Ab1!"),
+            ["Password must be at least 8 characters long"]
+        );
+        assert_eq!(
+            check(&policy(), "Ab1!Ab1!Ab1!Ab1!A"),
+            ["Password must be at most 16 characters long"]
+        );
+    }
+
+    #[test]
+    fn check_character_classes() {
+        assert_eq!(
+            check(&policy(), "tr0ub4dor&3"),
+            ["Password must contain an uppercase letter"]
+        );
+        assert_eq!(
+            check(&policy(), "TR0UB4DOR&3"),
+            ["Password must contain a lowercase letter"]
+        );
+        assert_eq!(
+            check(&policy(), "Troubador&"),
+            ["Password must contain a digit"]
+        );
+        assert_eq!(
+            check(&policy(), "Tr0ub4dor3"),
+            ["Password must contain a symbol"]
+        );
+        // every broken rule is reported
+        assert_eq!(check(&policy(), "").len(), 5);
+
+        let lenient = PasswordPolicy {
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            ..policy()
+        };
+        assert!(check(&lenient, "troubador").is_empty());
+    }
+
+    #[test]
+    fn reject_user_info() {
+        for password in ["xAlice1!x", "xWONDER1!", "1A!wonder"] {
+            assert_eq!(
+                check(&policy(), password),
+                ["Password must not contain the username or email address"],
+            );
+        }
+        // too short to matter
+        assert!(policy()
+            .check("password", "Tr0ub4dor&3", "tr", "tr@example.com")
+            .is_ok());
+
+        let lenient = PasswordPolicy {
+            reject_user_info: false,
+            ..policy()
+        };
+        assert!(check(&lenient, "xAlice1!x").is_empty());
+    }
+
+    #[test]
+    fn reject_listed_breached_passwords() {
+        let dir = scratch_dir("list");
+        let list = dir.join("breached.txt");
+        fs::write(&list, "Password1!\n\nhunter2\n").unwrap();
+
+        let breached_passwords = BreachedPasswords::from_list(list.to_str().unwrap()).unwrap();
+        assert!(breached_passwords.contains("hunter2").unwrap());
+        assert!(!breached_passwords.contains("Hunter2").unwrap());
+        assert!(!breached_passwords.contains("").unwrap());
+
+        let policy = PasswordPolicy {
+            breached_passwords: Some(breached_passwords),
+            ..policy()
+        };
+        assert_eq!(
+            check(&policy, "Password1!"),
+            ["Password is known to have been breached"]
+        );
+
+        assert!(BreachedPasswords::from_list(dir.join("missing").to_str().unwrap()).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reject_ranged_breached_passwords() {
+        let dir = scratch_dir("ranges");
+        // SHA-1 of hunter2: F3BBBD66A63D4BF1747940578EC3D0103530E21D
+        fs::write(
+            dir.join("F3BBB.txt"),
+            "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\nd66a63d4bf1747940578ec3d0103530e21d:17\r\n",
+        )
+        .unwrap();
+
+        let breached_passwords = BreachedPasswords::from_ranges(dir.to_str().unwrap()).unwrap();
+        assert!(breached_passwords.contains("hunter2").unwrap());
+        assert!(!breached_passwords.contains("hunter3").unwrap());
+
+        assert!(BreachedPasswords::from_ranges(dir.join("F3BBB.txt").to_str().unwrap()).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
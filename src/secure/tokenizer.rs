@@ -1,41 +1,1250 @@
-use crate::error::Error;
+use crate::{
+    db::model::User,
+    error::Error,
+    secure::{
+        jwks::TrustedIssuer,
+        keys::{derive_key, Algorithm, EncryptionKey, Jwks, SigningKey, VerifyingKey},
+        paseto::PasetoKey,
+    },
+};
 
-use jwt_simple::prelude::*;
+use hmac::{Hmac, Mac};
+use jwt_simple::{
+    prelude::*,
+    reexports::{ct_codecs::Hex, rand::RngCore},
+    JWTError,
+};
+use rocket_okapi::JsonSchema;
 use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+/// Role granted to every user
+pub const ROLE_USER: &str = "user";
+/// Role granted to admin users
+pub const ROLE_ADMIN: &str = "admin";
+
+/// Roles of a user
+pub fn roles(is_admin: bool) -> Vec<String> {
+    if is_admin {
+        vec![ROLE_USER.to_string(), ROLE_ADMIN.to_string()]
+    } else {
+        vec![ROLE_USER.to_string()]
+    }
+}
+
+/// Scope to list the users
+pub const SCOPE_USERS_READ: &str = "users:read";
+/// Scope to create and delete users
+pub const SCOPE_USERS_WRITE: &str = "users:write";
+/// Scope to manage the own account: password and personal access tokens
+pub const SCOPE_ACCOUNT: &str = "account";
+/// Scope to introspect tokens
+pub const SCOPE_INTROSPECT: &str = "introspect";
+/// Scope to register and delete the OAuth clients
+pub const SCOPE_CLIENTS: &str = "clients";
+/// All the scopes a personal access token can be granted
+pub const SCOPES: [&str; 5] = [
+    SCOPE_USERS_READ,
+    SCOPE_USERS_WRITE,
+    SCOPE_ACCOUNT,
+    SCOPE_INTROSPECT,
+    SCOPE_CLIENTS,
+];
+/// OpenID Connect scope, asking for an ID token
+pub const SCOPE_OPENID: &str = "openid";
+/// OpenID Connect scope, asking for the email claims
+pub const SCOPE_EMAIL: &str = "email";
+/// The OpenID Connect scopes a user can request, besides the scopes of the API
+pub const OPENID_SCOPES: [&str; 2] = [SCOPE_OPENID, SCOPE_EMAIL];
+/// The scopes an OAuth client can be granted, the others are only meaningful for users
+pub const CLIENT_SCOPES: [&str; 3] = [SCOPE_USERS_READ, SCOPE_USERS_WRITE, SCOPE_INTROSPECT];
+
+/// Prefix of the personal access tokens, telling them apart from session tokens
+pub const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "rjpat_";
+/// Longest lifetime of a personal access token set to expire: 10 years, in seconds
+pub const MAX_PERSONAL_ACCESS_TOKEN_LIFETIME: u64 = 10 * 365 * 24 * 60 * 60;
+/// Key id of the action tokens, which none of the keys verifying the access tokens has
+const ACTION_KEY_ID: &str = "action";
+/// HKDF label of the key the stored tokens are digested with
+const DIGEST_KEY_LABEL: &str = "rocketjwt token digest";
+/// HKDF label of the key the action tokens are signed with
+const ACTION_KEY_LABEL: &str = "rocketjwt action token";
+
+/// Application specific claims embedded in the tokens
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UserClaims {
+    pub is_admin: bool,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Login session (refresh token family) the token belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Login time of the session, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<u64>,
+    /// OAuth client the token was issued to, for the client credentials grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Space separated scopes the token is restricted to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Admin impersonating the subject (RFC 8693)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+/// Party acting on behalf of the subject of a token (RFC 8693 `act` claim)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+pub struct Actor {
+    pub sub: String,
+}
+
+/// OpenID Connect claims embedded in the ID tokens
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IdTokenClaims {
+    pub email: String,
+    pub email_verified: bool,
+    /// Login time of the session, in seconds since the epoch
+    pub auth_time: u64,
+    /// Login session the ID token belongs to
+    pub sid: String,
+}
+
+/// The single action an action token can be used for
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+/// Application specific claims embedded in the action tokens
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionClaims {
+    pub purpose: ActionPurpose,
+    /// Email address to verify, the token is void once the user has another one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+/// Claims of a verified action token
+#[derive(Clone, Debug)]
+pub struct VerifiedAction {
+    /// Username the action applies to (`sub`)
+    pub subject: String,
+    /// Single use nonce (`nonce`), to be recorded once the action is done
+    pub nonce: String,
+    /// Expiry time, in seconds since the epoch (`exp`)
+    pub expires_at: u64,
+    /// Email address to verify, for the email verification tokens
+    pub email: Option<String>,
+}
+
+/// Claims of a verified token
+#[derive(Clone, Debug, Default)]
+pub struct TokenClaims {
+    /// Username the token was issued to (`sub`)
+    pub subject: String,
+    /// Unique token id (`jti`)
+    pub token_id: String,
+    /// Issue time, in seconds since the epoch (`iat`)
+    pub issued_at: u64,
+    /// Expiry time, in seconds since the epoch (`exp`), none for a personal access token
+    /// that never expires
+    pub expires_at: Option<u64>,
+    pub is_admin: bool,
+    pub roles: Vec<String>,
+    /// Login session the token belongs to (`sid`)
+    pub session_id: Option<String>,
+    /// Login time of the session, in seconds since the epoch (`auth_time`)
+    pub auth_time: Option<u64>,
+    /// OAuth client the token was issued to (`client_id`), the subject as well
+    pub client_id: Option<String>,
+    /// Issued by the trusted issuer, the subject being mapped from its configured claim
+    pub external: bool,
+    /// Admin impersonating the subject (`act.sub`), the subject being the impersonated user
+    pub actor: Option<String>,
+    /// Scopes of a personal access token or client token, session tokens are not restricted
+    pub scopes: Option<Vec<String>>,
+}
+
+impl TryFrom<JWTClaims<UserClaims>> for TokenClaims {
+    type Error = Error;
+
+    fn try_from(claims: JWTClaims<UserClaims>) -> Result<Self, Self::Error> {
+        match (
+            claims.subject,
+            claims.jwt_id,
+            claims.issued_at,
+            claims.expires_at,
+        ) {
+            (Some(subject), Some(token_id), Some(issued_at), Some(expires_at)) => Ok(Self {
+                subject,
+                token_id,
+                issued_at: issued_at.as_secs(),
+                expires_at: Some(expires_at.as_secs()),
+                is_admin: claims.custom.is_admin,
+                roles: claims.custom.roles,
+                session_id: claims.custom.sid,
+                auth_time: claims.custom.auth_time,
+                client_id: claims.custom.client_id,
+                external: false,
+                actor: claims.custom.act.map(|act| act.sub),
+                scopes: claims
+                    .custom
+                    .scope
+                    .map(|scope| scope.split_whitespace().map(String::from).collect()),
+            }),
+            _ => Err(Error::UnauthenticatedUser),
+        }
+    }
+}
+
+/// Issuer and audiences stamped into the tokens, and enforced on verification
+#[derive(Clone, Debug)]
+pub struct TokenValidation {
+    /// Issuer (`iss`) of the tokens, the only one accepted
+    pub issuer: String,
+    /// Audiences (`aud`) of the tokens, at least one of them must be present
+    pub audiences: Vec<String>,
+    /// Tolerated clock skew when checking the token timestamps
+    pub leeway: Duration,
+    /// Reject tokens issued longer ago than this, whatever their expiry
+    pub max_token_age: Option<Duration>,
+}
+
+/// Renewal of the tokens still in use, bounded by a maximum session age
+#[derive(Clone, Debug)]
+pub struct SlidingExpiration {
+    /// Fraction of its lifetime after which a token is renewed
+    pub renew_after: f64,
+    /// Maximum age of a login session, renewed tokens never outlive it
+    pub max_session_age: Duration,
+}
+
+/// A former signing key, still accepted for verification until `expires_at`
+struct RetiredKey {
+    key: VerifyingKey,
+    expires_at: SystemTime,
+}
 
-#[derive(Clone)]
 pub struct Tokenizer {
-    key: HS256Key,
+    signing_key: SigningKey,
+    verifying_key: VerifyingKey,
+    retired_keys: Vec<RetiredKey>,
+    encryption_key: Option<EncryptionKey>,
+    paseto_key: Option<PasetoKey>,
+    trusted_issuer: Option<TrustedIssuer>,
+    sliding_expiration: Option<SlidingExpiration>,
+    validation: TokenValidation,
+    digest_key: Hmac<Sha256>,
+    action_key: HS256Key,
     token_expiration: Duration,
+    refresh_token_expiration: Duration,
+    impersonation_token_expiration: Duration,
+    action_token_expiration: Duration,
 }
 
 pub fn hash(data: &str) -> String {
     format!("{:x}", Sha256::digest(data.as_bytes()))
 }
 
+/// Generate a random, hex encoded, identifier of `len` bytes
+pub fn random_id(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    jwt_simple::reexports::rand::thread_rng().fill_bytes(&mut bytes);
+
+    Hex::encode_to_string(bytes).unwrap_or_default()
+}
+
 impl Tokenizer {
-    pub fn new(token_expiration: impl Into<Duration>, secret_key: Option<&str>) -> Self {
-        let key = secret_key
-            .filter(|secret_key| !secret_key.is_empty())
-            .map(|secret_key| HS256Key::generate().with_key_id(secret_key))
-            .unwrap_or_else(HS256Key::generate);
+    pub fn new(
+        token_expiration: impl Into<Duration>,
+        refresh_token_expiration: impl Into<Duration>,
+        validation: TokenValidation,
+        digest_secret: &[u8],
+        signing_key: SigningKey,
+        verifying_key: Option<VerifyingKey>,
+    ) -> Result<Self, Error> {
+        let token_expiration = token_expiration.into();
+        let derived_key = signing_key.verifying_key();
+        let verifying_key = match verifying_key {
+            Some(key) if key.key_id() != derived_key.key_id() => {
+                return Err(Error::JwtKeyError(
+                    "public key does not match the private key".to_string(),
+                ))
+            }
+            Some(key) => key,
+            None => derived_key,
+        };
+        let digest_key =
+            Hmac::<Sha256>::new_from_slice(&derive_key(digest_secret, DIGEST_KEY_LABEL, 32)?)
+                .map_err(|e| Error::JwtKeyError(e.to_string()))?;
+        // action tokens have a key of their own, so that they never verify as access tokens
+        let action_key = derive_key(digest_secret, ACTION_KEY_LABEL, 32)?;
+
+        Ok(Self {
+            signing_key,
+            verifying_key,
+            retired_keys: vec![],
+            encryption_key: None,
+            paseto_key: None,
+            trusted_issuer: None,
+            sliding_expiration: None,
+            validation,
+            digest_key,
+            action_key: HS256Key::from_bytes(&action_key).with_key_id(ACTION_KEY_ID),
+            token_expiration,
+            refresh_token_expiration: refresh_token_expiration.into(),
+            impersonation_token_expiration: token_expiration,
+            action_token_expiration: token_expiration,
+        })
+    }
+
+    /// Keep accepting tokens signed by a retired key until `expires_at`
+    pub fn with_retired_key(mut self, key: VerifyingKey, expires_at: SystemTime) -> Self {
+        if key.key_id() != self.verifying_key.key_id() {
+            self.retired_keys.push(RetiredKey { key, expires_at });
+        }
+        self
+    }
+
+    /// Issue encrypted tokens (JWE) instead of signed ones
+    pub fn with_encryption_key(mut self, key: EncryptionKey) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Issue PASETO v4 tokens instead of JWT ones
+    pub fn with_paseto_key(mut self, key: PasetoKey) -> Self {
+        self.paseto_key = Some(key);
+        self
+    }
+
+    /// Issue the impersonation tokens with the given lifetime, instead of the one of the access tokens
+    pub fn with_impersonation_token_expiration(mut self, expiration: impl Into<Duration>) -> Self {
+        self.impersonation_token_expiration = expiration.into();
+        self
+    }
+
+    /// Issue the action tokens with the given lifetime, instead of the one of the access tokens
+    pub fn with_action_token_expiration(mut self, expiration: impl Into<Duration>) -> Self {
+        self.action_token_expiration = expiration.into();
+        self
+    }
+
+    /// Also accept the tokens of another issuer, the ones whose key id matches none of the own keys
+    pub fn with_trusted_issuer(mut self, trusted_issuer: TrustedIssuer) -> Self {
+        self.trusted_issuer = Some(trusted_issuer);
+        self
+    }
 
-        Self {
-            key,
-            token_expiration: token_expiration.into(),
+    /// The other issuer whose tokens are accepted, if any
+    pub fn trusted_issuer(&self) -> Option<&TrustedIssuer> {
+        self.trusted_issuer.as_ref()
+    }
+
+    /// Renew the tokens past a fraction of their lifetime
+    pub fn with_sliding_expiration(mut self, sliding_expiration: SlidingExpiration) -> Self {
+        self.sliding_expiration = Some(sliding_expiration);
+        self
+    }
+
+    /// All the keys currently accepted for verification, the active one first
+    fn verifying_keys(&self) -> impl Iterator<Item = &VerifyingKey> {
+        let now = SystemTime::now();
+
+        std::iter::once(&self.verifying_key).chain(
+            self.retired_keys
+                .iter()
+                .filter(move |retired| retired.expires_at > now)
+                .map(|retired| &retired.key),
+        )
+    }
+
+    /// Issue a token for the given user, within the given login session, restricted to the
+    /// scopes of the session if any
+    pub fn generate(
+        &self,
+        user: &User,
+        session_id: &str,
+        auth_time: u64,
+        scope: Option<&str>,
+    ) -> Result<String, Error> {
+        self.issue(
+            &user.username,
+            UserClaims {
+                is_admin: user.is_admin,
+                roles: roles(user.is_admin),
+                sid: Some(session_id.to_string()),
+                auth_time: Some(auth_time),
+                scope: scope.map(String::from),
+                ..Default::default()
+            },
+            self.token_expiration,
+        )
+    }
+
+    /// Issue a token for an admin to impersonate the given user, within a login session of its own,
+    /// along with its expiry time in seconds since the epoch; such tokens are never renewed
+    pub fn generate_impersonation_token(
+        &self,
+        user: &User,
+        session_id: &str,
+        actor: &str,
+    ) -> Result<(String, u64), Error> {
+        let token = self.issue(
+            &user.username,
+            UserClaims {
+                is_admin: user.is_admin,
+                roles: roles(user.is_admin),
+                sid: Some(session_id.to_string()),
+                act: Some(Actor {
+                    sub: actor.to_string(),
+                }),
+                ..Default::default()
+            },
+            self.impersonation_token_expiration,
+        )?;
+
+        Ok((
+            token,
+            (Clock::now_since_epoch() + self.impersonation_token_expiration).as_secs(),
+        ))
+    }
+
+    /// Issue a token for the given OAuth client, restricted to the given scopes
+    pub fn generate_client_token(
+        &self,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<String, Error> {
+        self.issue(
+            client_id,
+            UserClaims {
+                client_id: Some(client_id.to_string()),
+                scope: Some(scopes.join(" ")),
+                ..Default::default()
+            },
+            self.token_expiration,
+        )
+    }
+
+    /// Issue an OpenID Connect ID token for the given user, within the given login session.
+    ///
+    /// ID tokens are always signed (JWS), whatever the format of the access tokens,
+    /// for the audience of the client the user logged in through, if any; and only with
+    /// an asymmetric algorithm, for the clients to verify them without the secret key.
+    pub fn generate_id_token(
+        &self,
+        user: &User,
+        session_id: &str,
+        auth_time: u64,
+        client_id: Option<&str>,
+    ) -> Result<String, Error> {
+        if !self.issues_id_tokens() {
+            return Err(Error::JwtKeyError(
+                "ID tokens require an asymmetric jwt_algorithm".to_string(),
+            ));
+        }
+
+        let claims = Claims::with_custom_claims(
+            IdTokenClaims {
+                email: user.email.clone(),
+                email_verified: user.email_verified,
+                auth_time,
+                sid: session_id.to_string(),
+            },
+            self.token_expiration,
+        )
+        .with_subject(&user.username)
+        .with_issuer(&self.validation.issuer);
+        let claims = match client_id {
+            Some(client_id) => claims.with_audience(client_id),
+            None => self.with_audiences(claims),
+        };
+
+        self.signing_key.sign(claims)
+    }
+
+    /// Issue a single use token allowing the given user to perform the given action only,
+    /// along with its expiry time in seconds since the epoch.
+    ///
+    /// Action tokens are always signed (JWS) with a key of their own, never accepted as access tokens.
+    pub fn generate_action_token(
+        &self,
+        user: &User,
+        purpose: ActionPurpose,
+    ) -> Result<(String, u64), Error> {
+        let claims = Claims::with_custom_claims(
+            ActionClaims {
+                purpose,
+                email: (purpose == ActionPurpose::EmailVerification).then(|| user.email.clone()),
+            },
+            self.action_token_expiration,
+        )
+        .with_subject(&user.username)
+        .with_nonce(random_id(16))
+        .with_issuer(&self.validation.issuer);
+
+        Ok((
+            self.action_key.authenticate(claims)?,
+            (Clock::now_since_epoch() + self.action_token_expiration).as_secs(),
+        ))
+    }
+
+    /// Verify an action token issued for the given purpose; its nonce still has to be
+    /// checked against, and recorded in, the backend
+    pub fn verify_action_token(
+        &self,
+        token: &str,
+        purpose: ActionPurpose,
+    ) -> Result<VerifiedAction, Error> {
+        let claims = self.action_key.verify_token::<ActionClaims>(
+            token,
+            Some(VerificationOptions {
+                allowed_issuers: Some(HashSet::from([self.validation.issuer.clone()])),
+                required_key_id: Some(ACTION_KEY_ID.to_string()),
+                time_tolerance: Some(self.validation.leeway),
+                ..Default::default()
+            }),
+        )?;
+        if claims.custom.purpose != purpose {
+            return Err(Error::BadRequest(
+                "Token was issued for another action".to_string(),
+            ));
+        }
+
+        match (claims.subject, claims.nonce, claims.expires_at) {
+            (Some(subject), Some(nonce), Some(expires_at)) => Ok(VerifiedAction {
+                subject,
+                nonce,
+                expires_at: expires_at.as_secs(),
+                email: claims.custom.email,
+            }),
+            _ => Err(Error::BadRequest("Invalid action token".to_string())),
         }
     }
 
-    pub fn generate(&self) -> Result<String, Error> {
-        let claims = Claims::create(self.token_expiration);
+    /// Issuer (`iss`) of the tokens
+    pub fn issuer(&self) -> &str {
+        &self.validation.issuer
+    }
 
-        Ok(self.key.authenticate(claims)?)
+    /// Algorithm the tokens, and ID tokens, are signed with
+    pub fn signing_algorithm(&self) -> Algorithm {
+        self.signing_key.algorithm()
+    }
+
+    /// Whether ID tokens can be issued, which requires an asymmetric signing algorithm
+    pub fn issues_id_tokens(&self) -> bool {
+        self.signing_algorithm().is_asymmetric()
+    }
+
+    /// Lifetime of the issued access tokens
+    pub fn token_expiration(&self) -> Duration {
+        self.token_expiration
+    }
+
+    /// Issue a fresh token for the claims of a token past the renewal point of its
+    /// lifetime; `None` if it is not due yet, or if its session is too old
+    pub fn renew(&self, claims: &TokenClaims) -> Result<Option<String>, Error> {
+        let (sliding_expiration, auth_time, expires_at) = match (
+            &self.sliding_expiration,
+            claims.auth_time,
+            claims.expires_at,
+        ) {
+            (Some(sliding_expiration), Some(auth_time), Some(expires_at)) => {
+                (sliding_expiration, auth_time, expires_at)
+            }
+            _ => return Ok(None),
+        };
+        let now = Clock::now_since_epoch().as_secs();
+        let lifetime = expires_at.saturating_sub(claims.issued_at) as f64;
+        let session_expires_at = auth_time + sliding_expiration.max_session_age.as_secs();
+
+        if (now.saturating_sub(claims.issued_at) as f64) < lifetime * sliding_expiration.renew_after
+            || now >= session_expires_at
+        {
+            return Ok(None);
+        }
+
+        self.issue(
+            &claims.subject,
+            UserClaims {
+                is_admin: claims.is_admin,
+                roles: claims.roles.clone(),
+                sid: claims.session_id.clone(),
+                auth_time: claims.auth_time,
+                client_id: claims.client_id.clone(),
+                scope: claims.scopes.as_ref().map(|scopes| scopes.join(" ")),
+                act: claims.actor.clone().map(|sub| Actor { sub }),
+            },
+            self.token_expiration
+                .min(Duration::from_secs(session_expires_at - now)),
+        )
+        .map(Some)
+    }
+
+    /// Seal the given claims in the configured token format
+    fn issue(
+        &self,
+        subject: &str,
+        custom: UserClaims,
+        valid_for: Duration,
+    ) -> Result<String, Error> {
+        let claims = Claims::with_custom_claims(custom, valid_for)
+            .with_subject(subject)
+            .with_jwt_id(random_id(16))
+            .with_issuer(&self.validation.issuer);
+        let claims = self.with_audiences(claims);
+
+        match (&self.paseto_key, &self.encryption_key) {
+            (Some(key), _) => key.seal(claims),
+            // signed first, for the encrypted token to be authenticated by the signing key
+            (None, Some(key)) => key.encrypt(&self.signing_key.sign(claims)?, valid_for),
+            (None, None) => self.signing_key.sign(claims),
+        }
+    }
+
+    /// Stamp the configured audiences into the claims
+    fn with_audiences<C>(&self, claims: JWTClaims<C>) -> JWTClaims<C> {
+        match self.validation.audiences.as_slice() {
+            [] => claims,
+            [audience] => claims.with_audience(audience),
+            audiences => claims.with_audiences(audiences.iter().collect()),
+        }
+    }
+
+    /// Issue an opaque refresh token along with its expiry time, in seconds since the epoch
+    pub fn generate_refresh_token(&self) -> (String, u64) {
+        (
+            random_id(32),
+            (Clock::now_since_epoch() + self.refresh_token_expiration).as_secs(),
+        )
+    }
+
+    /// Keyed digest of an issued token, the only form in which tokens are stored
+    pub fn digest(&self, token: &str) -> String {
+        let mut mac = self.digest_key.clone();
+        mac.update(token.as_bytes());
+
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    /// Publishable JWK Set of the keys accepted by this tokenizer
+    pub fn jwks(&self) -> Jwks {
+        Jwks {
+            keys: self
+                .verifying_keys()
+                .filter_map(VerifyingKey::to_jwk)
+                .collect(),
+        }
     }
 
-    pub fn verify(&self, token: &str) -> Result<String, Error> {
-        Ok(self
-            .key
-            .verify_token::<NoCustomClaims>(token, None)
-            .map(|_| token.to_string())?)
+    /// Issuer, audience and time checks applied to every verified token
+    fn verification_options(&self) -> VerificationOptions {
+        VerificationOptions {
+            allowed_issuers: Some(HashSet::from([self.validation.issuer.clone()])),
+            allowed_audiences: (!self.validation.audiences.is_empty())
+                .then(|| self.validation.audiences.iter().cloned().collect()),
+            time_tolerance: Some(self.validation.leeway),
+            max_validity: self.validation.max_token_age,
+            ..Default::default()
+        }
+    }
+
+    /// Verify a token and return its claims
+    pub fn verify(&self, token: &str) -> Result<TokenClaims, Error> {
+        if token.starts_with("v4.") {
+            return match &self.paseto_key {
+                Some(key) => key.open(token, &self.validation)?.try_into(),
+                None => Err(Error::UnauthenticatedUser),
+            };
+        }
+
+        // encrypted tokens (JWE) have 5 segments, and carry a signed one (JWS)
+        if token.split('.').count() == 5 {
+            return match &self.encryption_key {
+                Some(key) => self.verify_signed(&key.decrypt(token, self.validation.leeway)?),
+                None => Err(Error::UnauthenticatedUser),
+            };
+        }
+
+        self.verify_signed(token)
+    }
+
+    /// Verify a signed token (JWS) and return its claims
+    fn verify_signed(&self, token: &str) -> Result<TokenClaims, Error> {
+        if token.split('.').count() != 3 {
+            return Err(Error::UnauthenticatedUser);
+        }
+
+        // select the key by the `kid` header, tokens without one can only be
+        // checked against the active key
+        let metadata = Token::decode_metadata(token)?;
+        let key = match metadata.key_id() {
+            Some(kid) => self.verifying_keys().find(|key| key.key_id() == kid),
+            None => Some(&self.verifying_key),
+        };
+
+        match (key, &self.trusted_issuer) {
+            // a key id unknown here may be one of the trusted issuer
+            (None, Some(trusted_issuer)) => {
+                self.verify_trusted(trusted_issuer, token, metadata.key_id())
+            }
+            (None, None) => Err(jwt_simple::Error::from(JWTError::KeyIdentifierMismatch).into()),
+            // without key id, the token may come from either issuer
+            (Some(key), Some(trusted_issuer)) if metadata.key_id().is_none() => key
+                .verify::<UserClaims>(token, Some(self.verification_options()))
+                .and_then(TokenClaims::try_from)
+                .or_else(|_| self.verify_trusted(trusted_issuer, token, None)),
+            (Some(key), _) => key
+                .verify::<UserClaims>(token, Some(self.verification_options()))?
+                .try_into(),
+        }
+    }
+
+    /// Verify a token of the trusted issuer
+    fn verify_trusted(
+        &self,
+        trusted_issuer: &TrustedIssuer,
+        token: &str,
+        key_id: Option<&str>,
+    ) -> Result<TokenClaims, Error> {
+        let claims = trusted_issuer.verify(token, key_id)?;
+
+        Ok(TokenClaims {
+            // revoking a token without id revokes its digest instead
+            token_id: match claims.token_id.is_empty() {
+                true => self.digest(token),
+                false => claims.token_id,
+            },
+            ..claims
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        secure::keys::Algorithm,
+        testing::{tokenizer, tokenizer_with, user, validation, SECRET_KEY},
+    };
+
+    #[test]
+    fn generate_and_verify() {
+        let tokenizer = tokenizer();
+
+        let token = tokenizer
+            .generate(&user(true), "session", 42, None)
+            .unwrap();
+        let claims = tokenizer.verify(&token).unwrap();
+        assert_eq!(claims.subject, "admin");
+        assert!(claims.is_admin);
+        assert_eq!(claims.roles, vec![ROLE_USER, ROLE_ADMIN]);
+        assert_eq!(claims.session_id.as_deref(), Some("session"));
+        assert_eq!(claims.auth_time, Some(42));
+        assert_eq!(claims.expires_at, Some(claims.issued_at + 300));
+        assert_eq!(claims.token_id.len(), 32);
+
+        let other = tokenizer
+            .verify(
+                &tokenizer
+                    .generate(&user(false), "session", 0, None)
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(!other.is_admin);
+        assert_eq!(other.roles, vec![ROLE_USER]);
+        assert_ne!(other.token_id, claims.token_id);
+        // session tokens are only restricted to the scopes requested at login
+        assert!(other.scopes.is_none());
+
+        let token = tokenizer
+            .generate(&user(false), "session", 42, Some("account openid"))
+            .unwrap();
+        assert_eq!(
+            tokenizer.verify(&token).unwrap().scopes,
+            Some(vec!["account".to_string(), "openid".to_string()])
+        );
+
+        // issued by and for this API
+        let claims = tokenizer
+            .verifying_key
+            .verify::<UserClaims>(&token, None)
+            .unwrap();
+        assert_eq!(claims.issuer.as_deref(), Some("rocketjwt"));
+        assert!(claims
+            .audiences
+            .unwrap()
+            .contains(&HashSet::from(["api".to_string()])));
+    }
+
+    #[test]
+    fn reject_tokens_missing_claims() {
+        let tokenizer = tokenizer();
+
+        let claims = Claims::with_custom_claims(UserClaims::default(), Duration::from_mins(5))
+            .with_issuer("rocketjwt")
+            .with_audience("api");
+        let token = tokenizer
+            .signing_key
+            .sign(claims.clone().with_subject("admin"))
+            .unwrap();
+        assert!(tokenizer.verify(&token).is_err());
+        let token = tokenizer
+            .signing_key
+            .sign(claims.with_jwt_id(random_id(16)))
+            .unwrap();
+        assert!(tokenizer.verify(&token).is_err());
+    }
+
+    #[test]
+    fn generate_client_tokens() {
+        let tokenizer = tokenizer();
+
+        let scopes = [SCOPE_USERS_READ.to_string(), SCOPE_INTROSPECT.to_string()];
+        let token = tokenizer.generate_client_token("client", &scopes).unwrap();
+        let claims = tokenizer.verify(&token).unwrap();
+        assert_eq!(claims.subject, "client");
+        assert_eq!(claims.client_id.as_deref(), Some("client"));
+        assert_eq!(claims.scopes, Some(scopes.to_vec()));
+        assert!(!claims.is_admin);
+        assert!(claims.session_id.is_none());
+
+        // session tokens are not restricted, and belong to no client
+        let claims = tokenizer
+            .verify(
+                &tokenizer
+                    .generate(&user(false), "session", 0, None)
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(claims.client_id.is_none());
+        assert!(claims.scopes.is_none());
+    }
+
+    #[test]
+    fn generate_id_tokens() {
+        // relying parties could not verify HS256 ID tokens
+        let tokenizer = tokenizer();
+        assert!(!tokenizer.issues_id_tokens());
+        assert!(tokenizer
+            .generate_id_token(&user(false), "session", 42, None)
+            .is_err());
+
+        let key = SigningKey::from_pem(
+            Algorithm::ES256,
+            &ES256KeyPair::generate().to_pem().unwrap(),
+        );
+        let tokenizer = tokenizer_with(validation(), key.unwrap());
+        assert!(tokenizer.issues_id_tokens());
+
+        let token = tokenizer
+            .generate_id_token(&user(false), "session", 42, Some("client"))
+            .unwrap();
+        let claims = tokenizer
+            .verifying_key
+            .verify::<IdTokenClaims>(&token, None)
+            .unwrap();
+        assert_eq!(claims.subject.as_deref(), Some("admin"));
+        assert_eq!(claims.issuer.as_deref(), Some("rocketjwt"));
+        assert_eq!(claims.custom.email, "admin@example.com");
+        assert_eq!(claims.custom.sid, "session");
+        assert_eq!(claims.custom.auth_time, 42);
+        assert!(claims
+            .audiences
+            .unwrap()
+            .contains(&HashSet::from(["client".to_string()])));
+
+        // for the API itself without client
+        let token = tokenizer
+            .generate_id_token(&user(false), "session", 42, None)
+            .unwrap();
+        let claims = tokenizer
+            .verifying_key
+            .verify::<IdTokenClaims>(&token, None)
+            .unwrap();
+        assert!(claims
+            .audiences
+            .unwrap()
+            .contains(&HashSet::from(["api".to_string()])));
+
+        // ID tokens are not access tokens
+        assert!(tokenizer.verify(&token).is_err());
+    }
+
+    #[test]
+    fn generate_impersonation_tokens() {
+        let tokenizer = tokenizer()
+            .with_impersonation_token_expiration(Duration::from_mins(2))
+            .with_sliding_expiration(SlidingExpiration {
+                renew_after: 0.0,
+                max_session_age: Duration::from_mins(60),
+            });
+
+        let (token, expires_at) = tokenizer
+            .generate_impersonation_token(&user(false), "session", "root")
+            .unwrap();
+        let claims = tokenizer.verify(&token).unwrap();
+        assert_eq!(claims.subject, "admin");
+        assert_eq!(claims.actor.as_deref(), Some("root"));
+        assert_eq!(claims.session_id.as_deref(), Some("session"));
+        assert!(!claims.is_admin);
+        assert_eq!(claims.expires_at, Some(expires_at));
+        assert_eq!(expires_at - claims.issued_at, 120);
+
+        // never renewed
+        assert!(tokenizer.renew(&claims).unwrap().is_none());
+
+        // the other tokens act on their own behalf
+        let claims = tokenizer
+            .verify(&tokenizer.generate(&user(true), "session", 0, None).unwrap())
+            .unwrap();
+        assert!(claims.actor.is_none());
+    }
+
+    #[test]
+    fn verify_action_tokens() {
+        let tokenizer = tokenizer().with_action_token_expiration(Duration::from_mins(15));
+
+        let (token, expires_at) = tokenizer
+            .generate_action_token(&user(false), ActionPurpose::EmailVerification)
+            .unwrap();
+        let action = tokenizer
+            .verify_action_token(&token, ActionPurpose::EmailVerification)
+            .unwrap();
+        assert_eq!(action.subject, "admin");
+        assert_eq!(action.email.as_deref(), Some("admin@example.com"));
+        assert_eq!(action.nonce.len(), 32);
+        assert_eq!(action.expires_at, expires_at);
+
+        // the password reset tokens carry no email address
+        let (token_reset, _) = tokenizer
+            .generate_action_token(&user(false), ActionPurpose::PasswordReset)
+            .unwrap();
+        let reset = tokenizer
+            .verify_action_token(&token_reset, ActionPurpose::PasswordReset)
+            .unwrap();
+        assert!(reset.email.is_none());
+        assert_ne!(reset.nonce, action.nonce);
+
+        assert!(matches!(
+            tokenizer.verify_action_token(&token, ActionPurpose::PasswordReset),
+            Err(Error::BadRequest(_))
+        ));
+        // action tokens are not access tokens, nor the other way around
+        assert!(tokenizer.verify(&token).is_err());
+        let access_token = tokenizer
+            .generate(&user(false), "session", 0, None)
+            .unwrap();
+        assert!(tokenizer
+            .verify_action_token(&access_token, ActionPurpose::PasswordReset)
+            .is_err());
+    }
+
+    #[test]
+    fn check_issuer_and_audiences() {
+        let token = tokenizer()
+            .generate(&user(false), "session", 0, None)
+            .unwrap();
+        let tokenizer_for =
+            |validation| tokenizer_with(validation, SigningKey::from_secret(SECRET_KEY).unwrap());
+
+        let other_issuer = tokenizer_for(TokenValidation {
+            issuer: "elsewhere".to_string(),
+            ..validation()
+        });
+        assert!(other_issuer.verify(&token).is_err());
+
+        // one of the audiences is enough
+        let audiences = tokenizer_for(TokenValidation {
+            audiences: vec!["api".to_string(), "admin".to_string()],
+            ..validation()
+        });
+        assert!(audiences.verify(&token).is_ok());
+        let token = audiences
+            .generate(&user(false), "session", 0, None)
+            .unwrap();
+        assert!(tokenizer().verify(&token).is_ok());
+
+        let other_audience = tokenizer_for(TokenValidation {
+            audiences: vec!["admin".to_string()],
+            ..validation()
+        });
+        let token = tokenizer()
+            .generate(&user(false), "session", 0, None)
+            .unwrap();
+        assert!(other_audience.verify(&token).is_err());
+    }
+
+    #[test]
+    fn check_the_token_age() {
+        let tokenizer = tokenizer();
+        let claims = Claims::with_custom_claims(UserClaims::default(), Duration::from_mins(5))
+            .with_subject("admin")
+            .with_jwt_id(random_id(16))
+            .with_issuer("rocketjwt")
+            .with_audience("api");
+        assert!(tokenizer
+            .verify(&tokenizer.signing_key.sign(claims.clone()).unwrap())
+            .is_ok());
+
+        let mut expired = claims.clone();
+        expired.expires_at = Some(Clock::now_since_epoch() - Duration::from_mins(1));
+        assert!(tokenizer
+            .verify(&tokenizer.signing_key.sign(expired).unwrap())
+            .is_err());
+
+        // issued too long ago, whatever its expiry
+        let mut old = claims;
+        old.issued_at = Some(Clock::now_since_epoch() - Duration::from_mins(10));
+        let max_age = tokenizer_with(
+            TokenValidation {
+                max_token_age: Some(Duration::from_mins(5)),
+                ..validation()
+            },
+            SigningKey::from_secret(SECRET_KEY).unwrap(),
+        );
+        let old = max_age.signing_key.sign(old).unwrap();
+        assert!(tokenizer.verify(&old).is_ok());
+        assert!(max_age.verify(&old).is_err());
+    }
+
+    #[test]
+    fn reject_tokens_of_other_keys() {
+        let other_key = SigningKey::from_secret(&"ab".repeat(32)).unwrap();
+        let token = tokenizer_with(validation(), other_key)
+            .generate(&user(false), "session", 0, None)
+            .unwrap();
+        assert!(tokenizer().verify(&token).is_err());
+
+        // unsigned tokens are never accepted
+        let unsigned = format!(
+            "{}.{}.",
+            Base64UrlSafeNoPadding::encode_to_string(r#"{"alg":"none"}"#).unwrap(),
+            token.split('.').nth(1).unwrap()
+        );
+        assert!(tokenizer().verify(&unsigned).is_err());
+        assert!(tokenizer().verify("garbage").is_err());
+    }
+
+    #[test]
+    fn publish_the_verifying_key() {
+        assert!(tokenizer().jwks().keys.is_empty());
+
+        let key = SigningKey::from_pem(Algorithm::EdDSA, &Ed25519KeyPair::generate().to_pem());
+        let tokenizer = tokenizer_with(validation(), key.unwrap());
+        let jwks = tokenizer.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].kid, tokenizer.verifying_key.key_id());
+    }
+
+    #[test]
+    fn accept_retired_keys_until_they_expire() {
+        let retired_key = || SigningKey::from_secret(&"ab".repeat(32)).unwrap();
+        let token = tokenizer_with(validation(), retired_key())
+            .generate(&user(false), "session", 0, None)
+            .unwrap();
+
+        let tokenizer = tokenizer().with_retired_key(
+            retired_key().verifying_key(),
+            SystemTime::now() + std::time::Duration::from_secs(60),
+        );
+        assert!(tokenizer.verify(&token).is_ok());
+        // symmetric keys are never published
+        assert!(tokenizer.jwks().keys.is_empty());
+
+        let tokenizer = self::tokenizer().with_retired_key(
+            retired_key().verifying_key(),
+            SystemTime::now() - std::time::Duration::from_secs(1),
+        );
+        assert!(tokenizer.verify(&token).is_err());
+    }
+
+    #[test]
+    fn publish_the_retired_keys() {
+        let key = || SigningKey::from_pem(Algorithm::EdDSA, &Ed25519KeyPair::generate().to_pem());
+        let retired_key = key().unwrap().verifying_key();
+        let expired_key = key().unwrap().verifying_key();
+        let tokenizer = tokenizer_with(validation(), key().unwrap())
+            .with_retired_key(
+                retired_key.clone(),
+                SystemTime::now() + std::time::Duration::from_secs(60),
+            )
+            .with_retired_key(
+                expired_key,
+                SystemTime::now() - std::time::Duration::from_secs(1),
+            );
+
+        let jwks = tokenizer.jwks();
+        assert_eq!(jwks.keys.len(), 2);
+        assert_eq!(jwks.keys[0].kid, tokenizer.verifying_key.key_id());
+        assert_eq!(jwks.keys[1].kid, retired_key.key_id());
+    }
+
+    #[test]
+    fn nest_signed_tokens_in_encrypted_ones() {
+        let encryption_key = || EncryptionKey::from_secret(&"cd".repeat(32)).unwrap();
+        let tokenizer = tokenizer().with_encryption_key(encryption_key());
+
+        let token = tokenizer
+            .generate(&user(false), "session", 0, None)
+            .unwrap();
+        assert_eq!(token.split('.').count(), 5);
+        assert_eq!(tokenizer.verify(&token).unwrap().subject, "admin");
+
+        // an encrypted token must carry a token signed by the signing key
+        let forged = SigningKey::from_secret(&"ab".repeat(32))
+            .unwrap()
+            .sign(
+                Claims::with_custom_claims(UserClaims::default(), Duration::from_mins(5))
+                    .with_subject("admin")
+                    .with_jwt_id(random_id(16))
+                    .with_issuer("rocketjwt")
+                    .with_audience("api"),
+            )
+            .unwrap();
+        for content in [forged.as_str(), "garbage", "a.b.c.d.e"] {
+            let token = encryption_key()
+                .encrypt(content, Duration::from_mins(5))
+                .unwrap();
+            assert!(tokenizer.verify(&token).is_err());
+        }
+
+        // encrypted tokens are only accepted with an encryption key
+        assert!(self::tokenizer().verify(&token).is_err());
+    }
+
+    #[test]
+    fn issue_paseto_tokens() {
+        let tokenizer = tokenizer_with(
+            TokenValidation {
+                audiences: vec![],
+                ..validation()
+            },
+            SigningKey::from_secret(SECRET_KEY).unwrap(),
+        )
+        .with_paseto_key(PasetoKey::from_secret(&"ef".repeat(32)).unwrap());
+
+        let token = tokenizer.generate(&user(true), "session", 0, None).unwrap();
+        assert!(token.starts_with("v4.local."));
+        let claims = tokenizer.verify(&token).unwrap();
+        assert_eq!(claims.subject, "admin");
+        assert!(claims.is_admin);
+        assert_eq!(claims.session_id.as_deref(), Some("session"));
+
+        // PASETO tokens are only accepted with a PASETO key
+        assert!(self::tokenizer().verify(&token).is_err());
+    }
+
+    #[test]
+    fn renew_tokens() {
+        let sliding = |renew_after| {
+            tokenizer().with_sliding_expiration(SlidingExpiration {
+                renew_after,
+                max_session_age: Duration::from_mins(60),
+            })
+        };
+        let tokenizer = sliding(0.0);
+        let now = Clock::now_since_epoch().as_secs();
+
+        let claims = tokenizer
+            .verify(
+                &tokenizer
+                    .generate(&user(false), "session", now, None)
+                    .unwrap(),
+            )
+            .unwrap();
+        let renewed = tokenizer
+            .verify(&tokenizer.renew(&claims).unwrap().unwrap())
+            .unwrap();
+        assert_ne!(renewed.token_id, claims.token_id);
+        assert_eq!(renewed.session_id, claims.session_id);
+        assert_eq!(renewed.auth_time, claims.auth_time);
+
+        // not past the renewal point yet
+        assert!(sliding(0.5).renew(&claims).unwrap().is_none());
+
+        // renewed tokens never outlive the session
+        let late = TokenClaims {
+            auth_time: Some(now - 3540),
+            ..claims.clone()
+        };
+        let renewed = tokenizer.renew(&late).unwrap().unwrap();
+        assert!(tokenizer.verify(&renewed).unwrap().expires_at.unwrap() <= now + 60);
+
+        // nor do the tokens that never expire get renewed
+        let endless = TokenClaims {
+            expires_at: None,
+            ..claims.clone()
+        };
+        assert!(tokenizer.renew(&endless).unwrap().is_none());
+
+        // the session is too old
+        let old = TokenClaims {
+            auth_time: Some(now - 3600),
+            ..claims.clone()
+        };
+        assert!(tokenizer.renew(&old).unwrap().is_none());
+
+        // never renewed without sliding expiration
+        assert!(self::tokenizer().renew(&claims).unwrap().is_none());
+    }
+
+    #[test]
+    fn generate_refresh_tokens() {
+        let tokenizer = tokenizer();
+        let now = Clock::now_since_epoch().as_secs();
+
+        let (refresh_token, expires_at) = tokenizer.generate_refresh_token();
+        assert_eq!(refresh_token.len(), 64);
+        assert!((now + 3600..=now + 3601).contains(&expires_at));
+        assert_ne!(refresh_token, tokenizer.generate_refresh_token().0);
+    }
+
+    #[test]
+    fn digest_tokens() {
+        let tokenizer = tokenizer();
+
+        assert_eq!(tokenizer.digest("token"), tokenizer.digest("token"));
+        assert_ne!(tokenizer.digest("token"), tokenizer.digest("other"));
+        assert_ne!(tokenizer.digest("token"), hash("token"));
+        assert_eq!(tokenizer.digest("token").len(), 64);
+
+        // keyed by the digest secret
+        let other_secret = Tokenizer::new(
+            Duration::from_mins(5),
+            Duration::from_mins(60),
+            validation(),
+            &[0xab; 32],
+            SigningKey::from_secret(SECRET_KEY).unwrap(),
+            None,
+        )
+        .unwrap();
+        assert_ne!(tokenizer.digest("token"), other_secret.digest("token"));
+    }
+
+    #[test]
+    fn reject_mismatched_key_pairs() {
+        let key = || SigningKey::from_pem(Algorithm::EdDSA, &Ed25519KeyPair::generate().to_pem());
+        let public_key = key().unwrap().verifying_key();
+
+        let tokenizer = |key, public_key| {
+            Tokenizer::new(
+                Duration::from_mins(5),
+                Duration::from_mins(60),
+                validation(),
+                SECRET_KEY.as_bytes(),
+                key,
+                Some(public_key),
+            )
+        };
+        assert!(tokenizer(key().unwrap(), public_key).is_err());
+        let key = key().unwrap();
+        let public_key = key.verifying_key();
+        assert!(tokenizer(key, public_key).is_ok());
     }
 }
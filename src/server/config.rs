@@ -1,15 +1,58 @@
 #![allow(unused_must_use)]
-use crate::{error::Error, secure::cert::generate_cert};
+use crate::{
+    error::Error,
+    secure::{
+        cert::generate_cert,
+        jwks::TrustedIssuer,
+        keys::{
+            decode_secret_key, derive_key, Algorithm, Encryption, EncryptionKey, SigningKey,
+            VerifyingKey, MIN_SECRET_KEY_LEN,
+        },
+        paseto::PasetoKey,
+        password::PasswordHasher,
+        password_policy::{BreachedPasswords, PasswordPolicy},
+        tokenizer::{SlidingExpiration, TokenValidation},
+    },
+};
+use jwt_simple::reexports::ct_codecs::{Base64, Encoder};
+use rocket::http::SameSite;
 use serde::{de, Deserialize, Deserializer};
-use std::{fs::File, io::Read, net::IpAddr, path::Path};
+use std::{fs::File, io::Read, net::IpAddr, path::Path, time::SystemTime};
 
 const SRV_ADDR: &str = "127.0.0.1";
 const SRV_PORT: usize = 8080;
 const SRV_KEEP_ALIVE: usize = 60;
 const SRV_FORMS_LIMIT: usize = 1024 * 256;
 const SRV_JSON_LIMIT: usize = 1024 * 256;
-const SRV_SECRET_KEY: &str = "t/xZkYvxfC8CSfTSH9ANiIR9t1SvLHqOYZ7vH4fp11s=";
-const JWT_TOKEN_EXPIRY: &str = "1 day";
+/// Secret keys published as defaults or samples, which anyone could sign tokens with
+const PUBLISHED_SECRET_KEYS: [&str; 2] = [
+    "t/xZkYvxfC8CSfTSH9ANiIR9t1SvLHqOYZ7vH4fp11s=",
+    "6E5A7234753778214125442A472D4B6150645367556B58703273357638792F42",
+];
+const JWT_TOKEN_EXPIRY: &str = "15 minutes";
+const JWT_REFRESH_TOKEN_EXPIRY: &str = "30 days";
+const JWT_IMPERSONATION_TOKEN_EXPIRY: &str = "5 minutes";
+const JWT_ACTION_TOKEN_EXPIRY: &str = "30 minutes";
+const JWT_ISSUER: &str = "rocketjwt";
+const JWT_AUDIENCE: &str = "rocketjwt";
+const JWT_LEEWAY: &str = "1 minute";
+const JWT_RENEW_AFTER: f64 = 0.5;
+const JWT_MAX_SESSION_AGE: &str = "12 hours";
+const RESOURCE_SERVER_USERNAME_CLAIM: &str = "sub";
+const RESOURCE_SERVER_LEEWAY: &str = "1 minute";
+const RESOURCE_SERVER_RELOAD_INTERVAL: &str = "30 seconds";
+/// HKDF label of the secret key of the private cookies
+const COOKIE_KEY_LABEL: &str = "rocketjwt cookies";
+const ARGON2_MEMORY_COST: u32 = 19 * 1024;
+const ARGON2_TIME_COST: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+const PASSWORD_MIN_LENGTH: usize = 8;
+const PASSWORD_MAX_LENGTH: usize = 128;
+const SESSION_COOKIE_NAME: &str = "rocketjwt_token";
+const CSRF_COOKIE_NAME: &str = "rocketjwt_csrf";
+const REFRESH_COOKIE_NAME: &str = "rocketjwt_refresh";
+const CSRF_HEADER_NAME: &str = "X-CSRF-Token";
+const SESSION_COOKIE_SECURE: bool = true;
 
 const SSL_ENABLED: bool = false;
 const SSL_GENERATE_SELF_SIGNED: bool = true;
@@ -55,7 +98,7 @@ impl TryFrom<config::Config> for Settings {
     type Error = Error;
 
     fn try_from(cfg: config::Config) -> Result<Self, Self::Error> {
-        cfg.try_into().map_err(|_| Error::ConfigurationError)
+        cfg.try_deserialize().map_err(|_| Error::ConfigurationError)
     }
 }
 
@@ -77,12 +120,568 @@ pub struct ServerConfig {
     /// JSON transfer limitation
     #[serde(default = "default_server_json_limit")]
     pub json_limit: usize,
-    /// Api Server Secret key
-    #[serde(default = "default_server_secret_key")]
+    /// Api Server Secret key (raw, hex or base64), also used to sign the JWT tokens; required
+    #[serde(default)]
     pub secret_key: String,
+    /// Secret key (raw, hex or base64) the stored tokens are digested with, and the action tokens
+    /// signed with; independent of `secret_key` so that rotating it keeps the sessions (defaults to it)
+    #[serde(default)]
+    pub token_digest_key: Option<String>,
     /// JWT token expiry
     #[serde(default = "default_server_jwt_expiry")]
     pub jwt_token_expiry: String,
+    /// Refresh token expiry
+    #[serde(default = "default_server_jwt_refresh_expiry")]
+    pub jwt_refresh_token_expiry: String,
+    /// Expiry of the tokens issued to admins impersonating a user
+    #[serde(default = "default_server_jwt_impersonation_expiry")]
+    pub jwt_impersonation_token_expiry: String,
+    /// Expiry of the single use action tokens (password reset & email verification)
+    #[serde(default = "default_server_jwt_action_expiry")]
+    pub jwt_action_token_expiry: String,
+    /// JWT signing algorithm: HS256, RS256, ES256 or EdDSA
+    #[serde(default)]
+    pub jwt_algorithm: Algorithm,
+    /// PEM private key file used to sign tokens (RS256, ES256 & EdDSA)
+    #[serde(default)]
+    pub jwt_private_key_file: Option<String>,
+    /// PEM public key file used to verify tokens (derived from the private key if not set)
+    #[serde(default)]
+    pub jwt_public_key_file: Option<String>,
+    /// JWE key management algorithm of the tokens: None (signed only), A256KW or ECDH-ES+A256KW
+    #[serde(default)]
+    pub jwt_encryption: Encryption,
+    /// 32 bytes secret key (raw, hex or base64) used to encrypt tokens (A256KW)
+    #[serde(default)]
+    pub jwt_encryption_key: Option<String>,
+    /// PEM P-256 private key file used to encrypt tokens (ECDH-ES+A256KW)
+    #[serde(default)]
+    pub jwt_encryption_key_file: Option<String>,
+    /// Format of the issued tokens: jwt, v4.local or v4.public (PASETO)
+    #[serde(default)]
+    pub token_format: TokenFormat,
+    /// 32 bytes secret key (raw, hex or base64) of the v4.local tokens
+    #[serde(default)]
+    pub paseto_local_key: Option<String>,
+    /// PEM Ed25519 private key file of the v4.public tokens
+    #[serde(default)]
+    pub paseto_private_key_file: Option<String>,
+    /// Former signing keys still accepted for verification
+    #[serde(default)]
+    pub jwt_retired_keys: Vec<RetiredKeyConfig>,
+    /// How long retired keys are accepted after `retired_at` (defaults to the token expiry)
+    #[serde(default)]
+    pub jwt_key_grace_period: Option<String>,
+    /// Issuer (`iss`) stamped into the tokens and required on verification
+    #[serde(default = "default_server_jwt_issuer")]
+    pub jwt_issuer: String,
+    /// Audiences (`aud`) stamped into the tokens, tokens must carry at least one of them
+    #[serde(default = "default_server_jwt_audiences")]
+    pub jwt_audiences: Vec<String>,
+    /// Tolerated clock skew when checking the token timestamps
+    #[serde(default = "default_server_jwt_leeway")]
+    pub jwt_leeway: String,
+    /// Reject tokens issued longer ago than this, whatever their expiry
+    #[serde(default)]
+    pub max_token_age: Option<String>,
+    /// Renew the tokens still in use instead of letting them expire
+    #[serde(default)]
+    pub jwt_sliding_expiration: Option<SlidingExpirationConfig>,
+    /// How the bearer guard validates tokens: strict or revocation_list
+    #[serde(default)]
+    pub jwt_verification: VerificationMode,
+    /// Clients allowed to introspect tokens, besides admin users
+    #[serde(default)]
+    pub introspection_clients: Vec<IntrospectionClient>,
+    /// Delivery of the tokens in cookies for browser clients, if enabled
+    pub session_cookie: Option<SessionCookieConfig>,
+    /// Public base URL of the server, advertised in the OpenID Connect discovery document
+    pub public_url: Option<String>,
+    /// Also accept the tokens of another issuer, if enabled
+    pub resource_server: Option<ResourceServerConfig>,
+    /// Cost of the Argon2id hashing of the user passwords
+    #[serde(default)]
+    pub password_hashing: PasswordHashingConfig,
+    /// Rules the passwords of the users must follow
+    #[serde(default)]
+    pub password_policy: PasswordPolicyConfig,
+}
+
+/// A client allowed to introspect tokens, authenticated with HTTP Basic
+#[derive(Deserialize, Clone, Debug)]
+pub struct IntrospectionClient {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// Public base URL of the server, the `Host` of the requests if not configured
+#[derive(Clone, Debug, Default)]
+pub struct PublicUrl(pub Option<String>);
+
+/// Token verification mode of the bearer guard
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationMode {
+    /// Verify the token and look its user up in the database,
+    /// so that tokens can be revoked instantly
+    #[default]
+    Strict,
+    /// Trust the claims of a validly signed, unexpired token without looking its user up;
+    /// the revocation list is still queried on every request, for logouts to take effect
+    #[serde(rename = "revocation_list", alias = "stateless")]
+    RevocationList,
+}
+
+/// Format of the issued tokens
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TokenFormat {
+    /// JSON Web Tokens, signed or encrypted (see `jwt_encryption`)
+    #[default]
+    #[serde(rename = "jwt")]
+    Jwt,
+    /// PASETO v4 tokens encrypted with a symmetric key
+    #[serde(rename = "v4.local")]
+    PasetoLocal,
+    /// PASETO v4 tokens signed with an Ed25519 key
+    #[serde(rename = "v4.public")]
+    PasetoPublic,
+}
+
+/// Sliding expiration of the tokens
+#[derive(Deserialize, Clone, Debug)]
+pub struct SlidingExpirationConfig {
+    /// Fraction of its lifetime after which a token is renewed (0 to 1)
+    #[serde(default = "default_jwt_renew_after")]
+    pub renew_after: f64,
+    /// Maximum age of a login session, renewed tokens never outlive it
+    #[serde(default = "default_jwt_max_session_age")]
+    pub max_session_age: String,
+}
+
+/// Another issuer whose tokens the bearer guard accepts (resource server mode)
+#[derive(Deserialize, Clone, Debug)]
+pub struct ResourceServerConfig {
+    /// JWK Set file of the public keys of the issuer, reloaded when it changes
+    pub jwks_file: String,
+    /// Issuer (`iss`) of the tokens
+    pub issuer: String,
+    /// Audiences (`aud`) the tokens must carry one of, not checked if empty
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// Claim holding the username the tokens are mapped to
+    #[serde(default = "default_resource_server_username_claim")]
+    pub username_claim: String,
+    /// Accept the tokens of users missing from the database, as ephemeral principals
+    #[serde(default)]
+    pub ephemeral_principals: bool,
+    /// Grant the admin rights of the user a token is mapped to; off by default, as whoever the
+    /// issuer names after an admin would be one
+    #[serde(default)]
+    pub map_admins: bool,
+    /// Tolerated clock skew with the issuer when checking the token timestamps
+    #[serde(default = "default_resource_server_leeway")]
+    pub leeway: String,
+    /// Reject the tokens of the issuer issued longer ago than this, whatever their expiry
+    #[serde(default)]
+    pub max_token_age: Option<String>,
+    /// Minimum interval between two checks of the JWK Set file for changes
+    #[serde(default = "default_resource_server_reload_interval")]
+    pub reload_interval: String,
+}
+
+/// Cost of the Argon2id password hashing, the stored hashes of another cost are
+/// rehashed on the next login of their user
+#[derive(Deserialize, Clone, Debug)]
+pub struct PasswordHashingConfig {
+    /// Memory cost, in KiB
+    #[serde(default = "default_argon2_memory_cost")]
+    pub memory_cost: u32,
+    /// Number of iterations
+    #[serde(default = "default_argon2_time_cost")]
+    pub time_cost: u32,
+    /// Degree of parallelism
+    #[serde(default = "default_argon2_parallelism")]
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashingConfig {
+    fn default() -> Self {
+        Self {
+            memory_cost: ARGON2_MEMORY_COST,
+            time_cost: ARGON2_TIME_COST,
+            parallelism: ARGON2_PARALLELISM,
+        }
+    }
+}
+
+/// Rules the passwords of the users must follow
+#[derive(Deserialize, Clone, Debug)]
+pub struct PasswordPolicyConfig {
+    /// Minimum length, in characters
+    #[serde(default = "default_password_min_length")]
+    pub min_length: usize,
+    /// Maximum length, in characters
+    #[serde(default = "default_password_max_length")]
+    pub max_length: usize,
+    #[serde(default)]
+    pub require_lowercase: bool,
+    #[serde(default)]
+    pub require_uppercase: bool,
+    #[serde(default)]
+    pub require_digit: bool,
+    /// Require a character that is neither a letter nor a digit
+    #[serde(default)]
+    pub require_symbol: bool,
+    /// Reject the passwords containing the username or the email address
+    #[serde(default = "default_password_reject_user_info")]
+    pub reject_user_info: bool,
+    /// Local list of breached passwords to reject
+    #[serde(default)]
+    pub breached_passwords_file: Option<String>,
+    /// Format of the breached passwords list
+    #[serde(default)]
+    pub breached_passwords_format: BreachedPasswordsFormat,
+}
+
+impl Default for PasswordPolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_length: PASSWORD_MIN_LENGTH,
+            max_length: PASSWORD_MAX_LENGTH,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            reject_user_info: true,
+            breached_passwords_file: None,
+            breached_passwords_format: BreachedPasswordsFormat::default(),
+        }
+    }
+}
+
+/// Format of the breached passwords list
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BreachedPasswordsFormat {
+    /// A file of passwords, one per line
+    #[default]
+    List,
+    /// A directory of k-anonymity range files of SHA-1 suffixes, one per 5 hex digits prefix
+    Ranges,
+}
+
+/// Delivery of the tokens in a private cookie, protected by a double-submit CSRF token
+#[derive(Deserialize, Clone, Debug)]
+pub struct SessionCookieConfig {
+    /// Name of the private (encrypted, HttpOnly) cookie carrying the access token
+    #[serde(default = "default_session_cookie_name")]
+    pub name: String,
+    /// Name of the private (encrypted, HttpOnly) cookie carrying the refresh token, only sent
+    /// to `/user/refresh`
+    #[serde(default = "default_refresh_cookie_name")]
+    pub refresh_cookie_name: String,
+    /// Name of the cookie carrying the CSRF token, readable by the front-end
+    #[serde(default = "default_csrf_cookie_name")]
+    pub csrf_cookie_name: String,
+    /// Request header state-changing requests must echo the CSRF token in
+    #[serde(default = "default_csrf_header_name")]
+    pub csrf_header_name: String,
+    /// Only send the cookies over HTTPS
+    #[serde(default = "default_session_cookie_secure")]
+    pub secure: bool,
+    /// SameSite policy of the cookies
+    #[serde(default)]
+    pub same_site: CookieSameSite,
+}
+
+/// SameSite policy of the session cookies
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    #[default]
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<CookieSameSite> for SameSite {
+    fn from(same_site: CookieSameSite) -> Self {
+        match same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        }
+    }
+}
+
+/// A former JWT signing key
+#[derive(Deserialize, Clone, Debug)]
+pub struct RetiredKeyConfig {
+    /// Algorithm the key was used with
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    /// Former server secret key (HS256)
+    pub secret_key: Option<String>,
+    /// Former PEM public key file (RS256, ES256 & EdDSA)
+    pub public_key_file: Option<String>,
+    /// When the key stopped signing tokens (RFC 3339, e.g. 2021-06-22T04:14:26Z)
+    pub retired_at: String,
+}
+
+impl ServerConfig {
+    /// Make sure the secret key was configured, long enough, and is not one of the published ones
+    pub fn check_secret_key(&self) -> crate::Result<()> {
+        let secret_key = decode_secret_key(&self.secret_key);
+
+        if secret_key.is_empty() {
+            Err(Error::MissingSecretKey)
+        } else if secret_key.len() < MIN_SECRET_KEY_LEN {
+            Err(Error::WeakSecretKey(MIN_SECRET_KEY_LEN))
+        } else if PUBLISHED_SECRET_KEYS
+            .iter()
+            .any(|published| decode_secret_key(published) == secret_key)
+        {
+            Err(Error::PublishedSecretKey)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Make sure the tokens are issued by the public URL, if any: OpenID Connect clients
+    /// check the issuer of the discovery document against the URL they fetched it from
+    pub fn check_public_url(&self) -> crate::Result<()> {
+        match &self.public_url {
+            Some(public_url)
+                if public_url.trim_end_matches('/') != self.jwt_issuer.trim_end_matches('/') =>
+            {
+                Err(Error::IssuerMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Secret the keys of the stored token digests and of the action tokens are derived from
+    pub fn token_digest_secret(&self) -> crate::Result<Vec<u8>> {
+        let secret = decode_secret_key(self.token_digest_key.as_ref().unwrap_or(&self.secret_key));
+
+        match secret.len() < MIN_SECRET_KEY_LEN {
+            true => Err(Error::WeakSecretKey(MIN_SECRET_KEY_LEN)),
+            false => Ok(secret),
+        }
+    }
+
+    /// Secret key of the private cookies, derived from the server secret key
+    pub fn cookie_secret_key(&self) -> crate::Result<String> {
+        let key = derive_key(&decode_secret_key(&self.secret_key), COOKIE_KEY_LABEL, 64)?;
+
+        Base64::encode_to_string(key).map_err(|e| Error::JwtKeyError(e.to_string()))
+    }
+
+    /// Load the key used to sign the JWT tokens
+    pub fn jwt_signing_key(&self) -> crate::Result<SigningKey> {
+        match (self.jwt_algorithm, &self.jwt_private_key_file) {
+            (Algorithm::HS256, _) => SigningKey::from_secret(&self.secret_key),
+            (algorithm, Some(key_file)) => {
+                SigningKey::from_pem(algorithm, &std::fs::read_to_string(key_file)?)
+            }
+            (algorithm, None) => Err(Error::JwtKeyError(format!(
+                "jwt_private_key_file is required for {:?}",
+                algorithm
+            ))),
+        }
+    }
+
+    /// Load the key used to encrypt the JWT tokens, if encryption is enabled
+    pub fn jwt_encryption_key(&self) -> crate::Result<Option<EncryptionKey>> {
+        match (
+            self.jwt_encryption,
+            &self.jwt_encryption_key,
+            &self.jwt_encryption_key_file,
+        ) {
+            (Encryption::None, _, _) => Ok(None),
+            (Encryption::A256KW, Some(secret_key), _) => {
+                Ok(Some(EncryptionKey::from_secret(secret_key)?))
+            }
+            (Encryption::EcdhEsA256KW, _, Some(key_file)) => Ok(Some(EncryptionKey::from_pem(
+                &std::fs::read_to_string(key_file)?,
+            )?)),
+            (Encryption::A256KW, None, _) => Err(Error::JwtKeyError(
+                "jwt_encryption_key is required for A256KW".to_string(),
+            )),
+            (Encryption::EcdhEsA256KW, _, None) => Err(Error::JwtKeyError(
+                "jwt_encryption_key_file is required for ECDH-ES+A256KW".to_string(),
+            )),
+        }
+    }
+
+    /// Load the key of the PASETO tokens, if PASETO is the token format
+    pub fn paseto_key(&self) -> crate::Result<Option<PasetoKey>> {
+        if self.token_format != TokenFormat::Jwt && self.jwt_audiences.len() > 1 {
+            return Err(Error::JwtKeyError(
+                "PASETO tokens carry a single audience, set at most one jwt_audiences".to_string(),
+            ));
+        }
+
+        match self.token_format {
+            TokenFormat::Jwt => Ok(None),
+            TokenFormat::PasetoLocal => match &self.paseto_local_key {
+                Some(secret_key) => Ok(Some(PasetoKey::from_secret(secret_key)?)),
+                None => Err(Error::JwtKeyError(
+                    "paseto_local_key is required for v4.local".to_string(),
+                )),
+            },
+            TokenFormat::PasetoPublic => match &self.paseto_private_key_file {
+                Some(key_file) => Ok(Some(PasetoKey::from_pem(&std::fs::read_to_string(
+                    key_file,
+                )?)?)),
+                None => Err(Error::JwtKeyError(
+                    "paseto_private_key_file is required for v4.public".to_string(),
+                )),
+            },
+        }
+    }
+
+    /// Load the retired keys along with the time they stop being accepted
+    pub fn jwt_retired_keys(&self) -> crate::Result<Vec<(VerifyingKey, SystemTime)>> {
+        let grace_period = parse_duration::parse(
+            self.jwt_key_grace_period
+                .as_deref()
+                .unwrap_or(&self.jwt_token_expiry),
+        )?;
+
+        self.jwt_retired_keys
+            .iter()
+            .map(|retired| {
+                let key = match (
+                    retired.algorithm,
+                    &retired.secret_key,
+                    &retired.public_key_file,
+                ) {
+                    (Algorithm::HS256, Some(secret_key), _) => {
+                        VerifyingKey::from_secret(secret_key)?
+                    }
+                    (Algorithm::HS256, None, _) => {
+                        return Err(Error::JwtKeyError(
+                            "secret_key is required for a retired HS256 key".to_string(),
+                        ))
+                    }
+                    (algorithm, _, Some(key_file)) => {
+                        VerifyingKey::from_pem(algorithm, &std::fs::read_to_string(key_file)?)?
+                    }
+                    (algorithm, _, None) => {
+                        return Err(Error::JwtKeyError(format!(
+                            "public_key_file is required for a retired {:?} key",
+                            algorithm
+                        )))
+                    }
+                };
+                let retired_at = humantime::parse_rfc3339_weak(&retired.retired_at)
+                    .map_err(|e| Error::JwtKeyError(format!("invalid retired_at: {}", e)))?;
+
+                Ok((key, retired_at + grace_period))
+            })
+            .collect()
+    }
+
+    /// Issuer, audience and time checks of the JWT tokens
+    pub fn jwt_validation(&self) -> crate::Result<TokenValidation> {
+        Ok(TokenValidation {
+            issuer: self.jwt_issuer.clone(),
+            audiences: self.jwt_audiences.clone(),
+            leeway: parse_duration::parse(&self.jwt_leeway)?.into(),
+            max_token_age: match &self.max_token_age {
+                Some(max_token_age) => Some(parse_duration::parse(max_token_age)?.into()),
+                None => None,
+            },
+        })
+    }
+
+    /// Hasher of the user passwords
+    pub fn password_hasher(&self) -> crate::Result<PasswordHasher> {
+        PasswordHasher::new(
+            self.password_hashing.memory_cost,
+            self.password_hashing.time_cost,
+            self.password_hashing.parallelism,
+        )
+    }
+
+    /// Rules the passwords of the users must follow, loading the breached passwords list if any
+    pub fn password_policy(&self) -> crate::Result<PasswordPolicy> {
+        let policy = &self.password_policy;
+        if policy.min_length > policy.max_length {
+            return Err(Error::ConfigurationError);
+        }
+
+        Ok(PasswordPolicy {
+            min_length: policy.min_length,
+            max_length: policy.max_length,
+            require_lowercase: policy.require_lowercase,
+            require_uppercase: policy.require_uppercase,
+            require_digit: policy.require_digit,
+            require_symbol: policy.require_symbol,
+            reject_user_info: policy.reject_user_info,
+            breached_passwords: match (
+                &policy.breached_passwords_file,
+                policy.breached_passwords_format,
+            ) {
+                (None, _) => None,
+                (Some(path), BreachedPasswordsFormat::List) => {
+                    Some(BreachedPasswords::from_list(path)?)
+                }
+                (Some(path), BreachedPasswordsFormat::Ranges) => {
+                    Some(BreachedPasswords::from_ranges(path)?)
+                }
+            },
+        })
+    }
+
+    /// Sliding expiration of the tokens, if enabled
+    pub fn jwt_sliding_expiration(&self) -> crate::Result<Option<SlidingExpiration>> {
+        match &self.jwt_sliding_expiration {
+            Some(sliding) if !(0.0..=1.0).contains(&sliding.renew_after) => {
+                Err(Error::ConfigurationError)
+            }
+            Some(sliding) => Ok(Some(SlidingExpiration {
+                renew_after: sliding.renew_after,
+                max_session_age: parse_duration::parse(&sliding.max_session_age)?.into(),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Load the public keys of the trusted issuer, in resource server mode
+    pub fn trusted_issuer(&self) -> crate::Result<Option<TrustedIssuer>> {
+        match &self.resource_server {
+            Some(resource_server) => Ok(Some(
+                TrustedIssuer::new(
+                    resource_server.issuer.clone(),
+                    resource_server.audiences.clone(),
+                    resource_server.username_claim.clone(),
+                    resource_server.ephemeral_principals,
+                    &resource_server.jwks_file,
+                )?
+                .with_admin_mapping(resource_server.map_admins)
+                .with_leeway(parse_duration::parse(&resource_server.leeway)?.into())
+                .with_max_token_age(match &resource_server.max_token_age {
+                    Some(max_token_age) => Some(parse_duration::parse(max_token_age)?.into()),
+                    None => None,
+                })
+                .with_reload_interval(parse_duration::parse(&resource_server.reload_interval)?),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Load the key used to verify the JWT tokens, if configured separately
+    pub fn jwt_verifying_key(&self) -> crate::Result<Option<VerifyingKey>> {
+        match (self.jwt_algorithm, &self.jwt_public_key_file) {
+            (Algorithm::HS256, _) | (_, None) => Ok(None),
+            (algorithm, Some(key_file)) => Ok(Some(VerifyingKey::from_pem(
+                algorithm,
+                &std::fs::read_to_string(key_file)?,
+            )?)),
+        }
+    }
 }
 
 impl Default for ServerConfig {
@@ -93,8 +692,35 @@ impl Default for ServerConfig {
             keep_alive: SRV_KEEP_ALIVE,
             forms_limit: SRV_FORMS_LIMIT,
             json_limit: SRV_JSON_LIMIT,
-            secret_key: SRV_SECRET_KEY.into(),
+            secret_key: String::new(),
+            token_digest_key: None,
             jwt_token_expiry: JWT_TOKEN_EXPIRY.into(),
+            jwt_refresh_token_expiry: JWT_REFRESH_TOKEN_EXPIRY.into(),
+            jwt_impersonation_token_expiry: JWT_IMPERSONATION_TOKEN_EXPIRY.into(),
+            jwt_action_token_expiry: JWT_ACTION_TOKEN_EXPIRY.into(),
+            jwt_algorithm: Algorithm::default(),
+            jwt_private_key_file: None,
+            jwt_public_key_file: None,
+            jwt_encryption: Encryption::default(),
+            jwt_encryption_key: None,
+            jwt_encryption_key_file: None,
+            token_format: TokenFormat::default(),
+            paseto_local_key: None,
+            paseto_private_key_file: None,
+            jwt_retired_keys: vec![],
+            jwt_key_grace_period: None,
+            jwt_issuer: JWT_ISSUER.into(),
+            jwt_audiences: default_server_jwt_audiences(),
+            jwt_leeway: JWT_LEEWAY.into(),
+            max_token_age: None,
+            jwt_sliding_expiration: None,
+            jwt_verification: VerificationMode::default(),
+            introspection_clients: vec![],
+            session_cookie: None,
+            public_url: None,
+            resource_server: None,
+            password_hashing: Default::default(),
+            password_policy: Default::default(),
         }
     }
 }
@@ -170,14 +796,102 @@ fn default_server_json_limit() -> usize {
     SRV_JSON_LIMIT
 }
 
-fn default_server_secret_key() -> String {
-    SRV_SECRET_KEY.into()
-}
-
 fn default_server_jwt_expiry() -> String {
     JWT_TOKEN_EXPIRY.into()
 }
 
+fn default_server_jwt_refresh_expiry() -> String {
+    JWT_REFRESH_TOKEN_EXPIRY.into()
+}
+
+fn default_server_jwt_impersonation_expiry() -> String {
+    JWT_IMPERSONATION_TOKEN_EXPIRY.into()
+}
+
+fn default_server_jwt_action_expiry() -> String {
+    JWT_ACTION_TOKEN_EXPIRY.into()
+}
+
+fn default_server_jwt_issuer() -> String {
+    JWT_ISSUER.into()
+}
+
+fn default_server_jwt_audiences() -> Vec<String> {
+    vec![JWT_AUDIENCE.into()]
+}
+
+fn default_server_jwt_leeway() -> String {
+    JWT_LEEWAY.into()
+}
+
+fn default_jwt_renew_after() -> f64 {
+    JWT_RENEW_AFTER
+}
+
+fn default_jwt_max_session_age() -> String {
+    JWT_MAX_SESSION_AGE.into()
+}
+
+// All resource server config defaults
+fn default_resource_server_username_claim() -> String {
+    RESOURCE_SERVER_USERNAME_CLAIM.into()
+}
+
+fn default_resource_server_leeway() -> String {
+    RESOURCE_SERVER_LEEWAY.into()
+}
+
+fn default_resource_server_reload_interval() -> String {
+    RESOURCE_SERVER_RELOAD_INTERVAL.into()
+}
+
+// All password hashing config defaults
+fn default_argon2_memory_cost() -> u32 {
+    ARGON2_MEMORY_COST
+}
+
+fn default_argon2_time_cost() -> u32 {
+    ARGON2_TIME_COST
+}
+
+fn default_argon2_parallelism() -> u32 {
+    ARGON2_PARALLELISM
+}
+
+// All password policy config defaults
+fn default_password_min_length() -> usize {
+    PASSWORD_MIN_LENGTH
+}
+
+fn default_password_max_length() -> usize {
+    PASSWORD_MAX_LENGTH
+}
+
+fn default_password_reject_user_info() -> bool {
+    true
+}
+
+// All session cookie config defaults
+fn default_session_cookie_name() -> String {
+    SESSION_COOKIE_NAME.into()
+}
+
+fn default_refresh_cookie_name() -> String {
+    REFRESH_COOKIE_NAME.into()
+}
+
+fn default_csrf_cookie_name() -> String {
+    CSRF_COOKIE_NAME.into()
+}
+
+fn default_csrf_header_name() -> String {
+    CSRF_HEADER_NAME.into()
+}
+
+fn default_session_cookie_secure() -> bool {
+    SESSION_COOKIE_SECURE
+}
+
 // All SSL config defaults
 fn default_ssl_enabled() -> bool {
     SSL_ENABLED
@@ -241,3 +955,121 @@ where
         None => Ok(None),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SECRET_KEY;
+    use jwt_simple::reexports::ct_codecs::Decoder;
+
+    fn server_config(secret_key: &str) -> ServerConfig {
+        ServerConfig {
+            secret_key: secret_key.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_secret_keys() {
+        assert!(server_config(SECRET_KEY).check_secret_key().is_ok());
+        assert!(matches!(
+            server_config("").check_secret_key(),
+            Err(Error::MissingSecretKey)
+        ));
+        assert!(matches!(
+            server_config("too short").check_secret_key(),
+            Err(Error::WeakSecretKey(MIN_SECRET_KEY_LEN))
+        ));
+        for published in PUBLISHED_SECRET_KEYS {
+            assert!(matches!(
+                server_config(published).check_secret_key(),
+                Err(Error::PublishedSecretKey)
+            ));
+        }
+    }
+
+    #[test]
+    fn check_the_public_url() {
+        let config = |public_url: Option<&str>, jwt_issuer: &str| ServerConfig {
+            public_url: public_url.map(String::from),
+            jwt_issuer: jwt_issuer.to_string(),
+            ..Default::default()
+        };
+
+        assert!(config(None, "rocketjwt").check_public_url().is_ok());
+        assert!(
+            config(Some("https://auth.example.com"), "https://auth.example.com")
+                .check_public_url()
+                .is_ok()
+        );
+        assert!(config(
+            Some("https://auth.example.com/"),
+            "https://auth.example.com"
+        )
+        .check_public_url()
+        .is_ok());
+        assert!(matches!(
+            config(Some("https://auth.example.com"), "rocketjwt").check_public_url(),
+            Err(Error::IssuerMismatch)
+        ));
+    }
+
+    #[test]
+    fn digest_with_a_dedicated_key() {
+        let config = server_config(SECRET_KEY);
+        assert_eq!(
+            config.token_digest_secret().unwrap(),
+            decode_secret_key(SECRET_KEY)
+        );
+
+        let config = ServerConfig {
+            token_digest_key: Some("ab".repeat(32)),
+            ..config
+        };
+        assert_eq!(config.token_digest_secret().unwrap(), vec![0xab; 32]);
+
+        let config = ServerConfig {
+            token_digest_key: Some("too short".to_string()),
+            ..config
+        };
+        assert!(matches!(
+            config.token_digest_secret(),
+            Err(Error::WeakSecretKey(MIN_SECRET_KEY_LEN))
+        ));
+    }
+
+    #[test]
+    fn derive_the_cookie_secret_key() {
+        let cookie_secret_key = server_config(SECRET_KEY).cookie_secret_key().unwrap();
+
+        assert_eq!(
+            Base64::decode_to_vec(&cookie_secret_key, None)
+                .unwrap()
+                .len(),
+            64
+        );
+        assert_ne!(
+            cookie_secret_key,
+            server_config(&"ab".repeat(32)).cookie_secret_key().unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_the_verification_modes() {
+        let parse = |mode: &str| {
+            jwt_simple::reexports::serde_json::from_value::<VerificationMode>(mode.into())
+        };
+
+        assert_eq!(parse("strict").unwrap(), VerificationMode::Strict);
+        assert_eq!(
+            parse("revocation_list").unwrap(),
+            VerificationMode::RevocationList
+        );
+        // the former name of the mode
+        assert_eq!(
+            parse("stateless").unwrap(),
+            VerificationMode::RevocationList
+        );
+        assert!(parse("none").is_err());
+    }
+}
@@ -1,4 +1,6 @@
 // All routes to be in here
 
 pub(crate) mod index;
+pub(crate) mod oauth;
 pub(crate) mod user;
+pub(crate) mod well_known;
@@ -0,0 +1,589 @@
+use crate::{
+    backends::{now, Backend},
+    db::model::{
+        ApiKey, Client, Introspection, IntrospectionRequest, NewClient, PartialClient, PartialUser,
+        RegisteredClient, TokenRequest, TokenResponse, User, UserCredentials, UserInfo,
+    },
+    error::{Error, OAuthError},
+    secure::{
+        password::PasswordHasher,
+        tokenizer::{
+            hash, random_id, Tokenizer, CLIENT_SCOPES, OPENID_SCOPES, SCOPES, SCOPE_ACCOUNT,
+            SCOPE_CLIENTS, SCOPE_INTROSPECT, SCOPE_OPENID,
+        },
+    },
+    server::{
+        config::{IntrospectionClient, VerificationMode},
+        controllers::user::issue_tokens,
+        guards::{
+            client::{ClientCredentials, ClientInfo},
+            jwt::validate_token,
+        },
+    },
+    Result,
+};
+
+use okapi::openapi3::Responses;
+use rocket::{
+    form::{self, Form},
+    request::Request,
+    response::{self, status::Created, Responder},
+    serde::json::{self, Json},
+    State,
+};
+use rocket_okapi::{gen::OpenApiGenerator, response::OpenApiResponderInner};
+
+/// Maximum length of a client id
+const MAX_CLIENT_ID_LEN: usize = 64;
+
+#[openapi(tag = "OAuth")]
+#[post("/token", data = "<request>")]
+pub fn token(
+    request: std::result::Result<Form<TokenRequest>, form::Errors<'_>>,
+    client: std::result::Result<ClientCredentials, Error>,
+    client_info: ClientInfo,
+    tokenizer: &State<Tokenizer>,
+    hasher: &State<PasswordHasher>,
+    backend: &State<Backend>,
+) -> std::result::Result<TokenResponse, OAuthError> {
+    let request = request?.into_inner();
+
+    // the client authenticates with HTTP Basic, or in the request body
+    let client = match (client, &request.client_id, &request.client_secret) {
+        (Ok(client), _, _) => Some(client),
+        (Err(_), Some(client_id), Some(client_secret)) => Some(ClientCredentials {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+        }),
+        _ => None,
+    };
+    let client = client
+        .map(|client| backend.find_client(&client.client_id, &hash(&client.client_secret)))
+        .transpose()?;
+
+    match request.grant_type.as_str() {
+        "client_credentials" => {
+            client_credentials_grant(client.ok_or(OAuthError::InvalidClient)?, request, tokenizer)
+        }
+        "password" => password_grant(request, client, client_info, tokenizer, hasher, backend),
+        "refresh_token" => refresh_token_grant(request, client, tokenizer, backend),
+        grant_type => Err(OAuthError::UnsupportedGrantType(format!(
+            "Unsupported grant type: {}",
+            grant_type
+        ))),
+    }
+}
+
+/// Issue an access token to a client, restricted to the scopes it requests
+fn client_credentials_grant(
+    client: Client,
+    request: TokenRequest,
+    tokenizer: &Tokenizer,
+) -> std::result::Result<TokenResponse, OAuthError> {
+    // only the granted scopes can be requested
+    let granted = client.scopes.split_whitespace().collect::<Vec<_>>();
+    let scopes = match &request.scope {
+        Some(scope) => {
+            let requested = scope
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            if let Some(scope) = requested
+                .iter()
+                .find(|scope| !granted.contains(&scope.as_str()))
+            {
+                return Err(OAuthError::InvalidScope(format!(
+                    "Scope not granted: {}",
+                    scope
+                )));
+            }
+            requested
+        }
+        None => granted.into_iter().map(String::from).collect(),
+    };
+
+    Ok(TokenResponse {
+        access_token: tokenizer.generate_client_token(&client.client_id, &scopes)?,
+        token_type: "Bearer".to_string(),
+        expires_in: tokenizer.token_expiration().as_secs(),
+        refresh_token: None,
+        scope: Some(scopes.join(" ")),
+        id_token: None,
+    })
+}
+
+/// Start a login session for the user with the given credentials
+fn password_grant(
+    request: TokenRequest,
+    client: Option<Client>,
+    client_info: ClientInfo,
+    tokenizer: &Tokenizer,
+    hasher: &PasswordHasher,
+    backend: &Backend,
+) -> std::result::Result<TokenResponse, OAuthError> {
+    let (username, password) = match (request.username, request.password) {
+        (Some(username), Some(password)) => (username, password),
+        _ => {
+            return Err(OAuthError::InvalidRequest(
+                "Missing username or password".to_string(),
+            ))
+        }
+    };
+    if let Some(scope) = request
+        .scope
+        .iter()
+        .flat_map(|scope| scope.split_whitespace())
+        .find(|scope| !SCOPES.contains(scope) && !OPENID_SCOPES.contains(scope))
+    {
+        return Err(OAuthError::InvalidScope(format!(
+            "Unknown scope: {}",
+            scope
+        )));
+    }
+    check_openid_scope(&request.scope, tokenizer)?;
+
+    let user = backend
+        .find_user(UserCredentials { username, password }, hasher)
+        .map_err(|e| match e {
+            Error::NotFound => OAuthError::InvalidGrant("Invalid username or password".to_string()),
+            e => e.into(),
+        })?;
+
+    // the tokens of the session are restricted to the requested scopes, if any
+    let mut scopes = Vec::new();
+    for scope in request
+        .scope
+        .iter()
+        .flat_map(|scope| scope.split_whitespace())
+    {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    let scope = (!scopes.is_empty()).then(|| scopes.join(" "));
+
+    let session_id = random_id(16);
+    let api_key = issue_tokens(
+        user.clone(),
+        session_id.clone(),
+        Some(client_info),
+        scope,
+        tokenizer,
+        backend,
+    )?;
+    let response = TokenResponse::from_api_key(api_key, &request.scope, tokenizer);
+
+    Ok(TokenResponse {
+        id_token: id_token(
+            &response.scope,
+            &user,
+            &session_id,
+            client,
+            tokenizer,
+            backend,
+        )?,
+        ..response
+    })
+}
+
+/// Rotate a refresh token, within its login session
+fn refresh_token_grant(
+    request: TokenRequest,
+    client: Option<Client>,
+    tokenizer: &Tokenizer,
+    backend: &Backend,
+) -> std::result::Result<TokenResponse, OAuthError> {
+    let refresh_token = request
+        .refresh_token
+        .ok_or_else(|| OAuthError::InvalidRequest("Missing refresh token".to_string()))?;
+    check_openid_scope(&request.scope, tokenizer)?;
+    // the session, its user or the refresh token itself being gone, the grant is invalid
+    let invalid_grant = |e| match e {
+        Error::UnauthenticatedUser | Error::NotFound => {
+            OAuthError::InvalidGrant("Invalid refresh token".to_string())
+        }
+        e => e.into(),
+    };
+
+    // rotate: the presented refresh token can never be used again
+    let refresh_token = backend
+        .use_refresh_token(&tokenizer.digest(&refresh_token))
+        .map_err(invalid_grant)?;
+    let session = backend
+        .get_session(&refresh_token.family_id)
+        .map_err(invalid_grant)?;
+
+    // the session keeps its scopes, none can be added (RFC 6749 section 6)
+    let granted = match &session.scope {
+        Some(scope) => scope.split_whitespace().collect::<Vec<_>>(),
+        None => SCOPES.to_vec(),
+    };
+    if let Some(scope) = request
+        .scope
+        .iter()
+        .flat_map(|scope| scope.split_whitespace())
+        .find(|scope| !granted.contains(scope))
+    {
+        return Err(OAuthError::InvalidScope(format!(
+            "Scope not granted: {}",
+            scope
+        )));
+    }
+
+    let user = backend
+        .get_user(&refresh_token.username)
+        .map_err(invalid_grant)?;
+    let api_key = issue_tokens(
+        user.clone(),
+        refresh_token.family_id.clone(),
+        None,
+        None,
+        tokenizer,
+        backend,
+    )
+    .map_err(invalid_grant)?;
+    let response = TokenResponse::from_api_key(api_key, &request.scope, tokenizer);
+
+    Ok(TokenResponse {
+        id_token: id_token(
+            &response.scope,
+            &user,
+            &refresh_token.family_id,
+            client,
+            tokenizer,
+            backend,
+        )?,
+        ..response
+    })
+}
+
+/// The `openid` scope can only be requested if ID tokens can be issued
+fn check_openid_scope(
+    scope: &Option<String>,
+    tokenizer: &Tokenizer,
+) -> std::result::Result<(), OAuthError> {
+    match scope
+        .iter()
+        .flat_map(|scope| scope.split_whitespace())
+        .any(|scope| scope == SCOPE_OPENID)
+    {
+        true if !tokenizer.issues_id_tokens() => Err(OAuthError::InvalidScope(
+            "The openid scope requires an asymmetric jwt_algorithm".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// ID token of a login session, if the `openid` scope was granted
+fn id_token(
+    scope: &Option<String>,
+    user: &User,
+    session_id: &str,
+    client: Option<Client>,
+    tokenizer: &Tokenizer,
+    backend: &Backend,
+) -> Result<Option<String>> {
+    if !scope
+        .iter()
+        .flat_map(|scope| scope.split_whitespace())
+        .any(|scope| scope == SCOPE_OPENID)
+    {
+        return Ok(None);
+    }
+    let auth_time = backend.get_session(session_id)?.created_at as u64;
+
+    tokenizer
+        .generate_id_token(
+            user,
+            session_id,
+            auth_time,
+            client.as_ref().map(|client| client.client_id.as_str()),
+        )
+        .map(Some)
+}
+
+impl TokenResponse {
+    /// Token response for the tokens of a login session, along with the scopes they are
+    /// restricted to; tokens not restricted by scopes are granted all of them, and the
+    /// OpenID Connect scopes requested
+    fn from_api_key(api_key: ApiKey, requested: &Option<String>, tokenizer: &Tokenizer) -> Self {
+        let scopes = match api_key.claims.scopes {
+            Some(scopes) => scopes,
+            None => SCOPES
+                .iter()
+                .copied()
+                .chain(OPENID_SCOPES.into_iter().filter(|openid_scope| {
+                    requested
+                        .iter()
+                        .flat_map(|scope| scope.split_whitespace())
+                        .any(|scope| scope == *openid_scope)
+                }))
+                .map(String::from)
+                .collect(),
+        };
+
+        Self {
+            access_token: api_key.token,
+            token_type: "Bearer".to_string(),
+            expires_in: tokenizer.token_expiration().as_secs(),
+            refresh_token: api_key.refresh_token,
+            scope: Some(scopes.join(" ")),
+            id_token: None,
+        }
+    }
+}
+
+/// Token responses must not be cached
+impl<'r> Responder<'r, 'static> for TokenResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        response::Response::build_from(Json(self).respond_to(request)?)
+            .raw_header("Cache-Control", "no-store")
+            .raw_header("Pragma", "no-cache")
+            .ok()
+    }
+}
+
+impl OpenApiResponderInner for TokenResponse {
+    fn responses(generator: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        Json::<TokenResponse>::responses(generator)
+    }
+}
+
+#[openapi(tag = "OAuth")]
+#[post("/introspect", data = "<request>")]
+pub fn introspect(
+    request: std::result::Result<Form<IntrospectionRequest>, form::Errors<'_>>,
+    client: std::result::Result<ClientCredentials, Error>,
+    api_key: std::result::Result<ApiKey, Error>,
+    clients: &State<Vec<IntrospectionClient>>,
+    tokenizer: &State<Tokenizer>,
+    backend: &State<Backend>,
+    verification: &State<VerificationMode>,
+) -> Result<Json<Introspection>> {
+    // only the configured or allowed clients and admin users may introspect tokens
+    match client {
+        Ok(client) => {
+            if !clients.iter().any(|known| {
+                known.client_id == client.client_id
+                    && hash(&known.client_secret) == hash(&client.client_secret)
+            }) && !backend
+                .find_client(&client.client_id, &hash(&client.client_secret))
+                .map(|client| {
+                    client
+                        .scopes
+                        .split_whitespace()
+                        .any(|s| s == SCOPE_INTROSPECT)
+                })
+                .unwrap_or_default()
+            {
+                return Err(Error::UnauthenticatedUser);
+            }
+        }
+        Err(_) => {
+            let _ = api_key?.require_admin()?.require_scope(SCOPE_INTROSPECT)?;
+        }
+    }
+    let request = request?;
+
+    // any invalid, expired or revoked token is simply inactive
+    Ok(Json(
+        validate_token(&request.token, tokenizer, backend, *verification.inner())
+            .map(Introspection::from)
+            .unwrap_or_default(),
+    ))
+}
+
+#[openapi(tag = "OAuth")]
+#[get("/userinfo")]
+pub fn userinfo(
+    api_key: std::result::Result<ApiKey, Error>,
+    backend: &State<Backend>,
+) -> Result<Json<UserInfo>> {
+    // client tokens have no user, and are never granted these scopes
+    let api_key = api_key?.require_any_scope(&[SCOPE_ACCOUNT, SCOPE_OPENID])?;
+
+    backend
+        .get_user(&api_key.claims.subject)
+        .map(|user| Json(UserInfo::from(PartialUser::from(user))))
+}
+
+#[openapi(tag = "OAuth")]
+#[post("/userinfo")]
+pub fn post_userinfo(
+    api_key: std::result::Result<ApiKey, Error>,
+    backend: &State<Backend>,
+) -> Result<Json<UserInfo>> {
+    userinfo(api_key, backend)
+}
+
+#[openapi(tag = "OAuth")]
+#[post("/clients", data = "<client>")]
+pub fn add_client(
+    client: std::result::Result<Json<NewClient>, json::Error<'_>>,
+    api_key: std::result::Result<ApiKey, Error>,
+    backend: &State<Backend>,
+) -> Result<Created<Json<RegisteredClient>>> {
+    let _ = api_key?.require_admin()?.require_scope(SCOPE_CLIENTS)?;
+    let client = client?.into_inner();
+
+    if client.client_id.is_empty()
+        || client.client_id.len() > MAX_CLIENT_ID_LEN
+        || client.client_id.contains(':')
+    {
+        return Err(Error::BadRequest(format!(
+            "Client id must be between 1 and {} characters long, without ':'",
+            MAX_CLIENT_ID_LEN
+        )));
+    }
+    if let Some(scope) = client
+        .scopes
+        .iter()
+        .find(|scope| !CLIENT_SCOPES.contains(&scope.as_str()))
+    {
+        return Err(Error::BadRequest(format!("Unknown scope: {}", scope)));
+    }
+
+    let mut scopes = client.scopes;
+    scopes.sort();
+    scopes.dedup();
+    let client_secret = random_id(32);
+    let client = Client {
+        client_id: client.client_id,
+        secret_hash: hash(&client_secret),
+        scopes: scopes.join(" "),
+        created_at: now(),
+    };
+    backend.add_client(client.clone())?;
+
+    let location = format!("/oauth/clients/{}", client.client_id);
+    Ok(Created::new(location).body(Json(RegisteredClient {
+        info: client.into(),
+        client_secret,
+    })))
+}
+
+#[openapi(tag = "OAuth")]
+#[get("/clients")]
+pub fn get_all_clients(
+    api_key: std::result::Result<ApiKey, Error>,
+    backend: &State<Backend>,
+) -> Result<Json<Vec<PartialClient>>> {
+    let _ = api_key?.require_admin()?.require_scope(SCOPE_CLIENTS)?;
+
+    Ok(Json(
+        backend
+            .list_clients()?
+            .into_iter()
+            .map(PartialClient::from)
+            .collect(),
+    ))
+}
+
+#[openapi(tag = "OAuth")]
+#[delete("/clients/<client_id>")]
+pub fn delete_client(
+    client_id: String,
+    api_key: std::result::Result<ApiKey, Error>,
+    backend: &State<Backend>,
+) -> Result<()> {
+    let _ = api_key?.require_admin()?.require_scope(SCOPE_CLIENTS)?;
+
+    backend.delete_client(&client_id)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use crate::server::testing::{client, rs256_settings, PASSWORD};
+
+    use diesel::{connection::SimpleConnection, Connection, SqliteConnection};
+    use rocket::{
+        http::{ContentType, Status},
+        local::blocking::Client,
+        serde::json::Value,
+    };
+
+    /// Token response of a token request, along with its status
+    fn token(client: &Client, body: String) -> (Status, Value) {
+        let response = client
+            .post("/oauth/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+
+        (response.status(), response.into_json().unwrap())
+    }
+
+    /// Log the `user` user in, restricted to the given scopes if any
+    fn login(client: &Client, scope: Option<&str>) -> Value {
+        let scope = scope.map(|scope| format!("&scope={}", scope));
+        let (status, response) = token(
+            client,
+            format!(
+                "grant_type=password&username=user&password={}{}",
+                PASSWORD.replace('&', "%26"),
+                scope.unwrap_or_default()
+            ),
+        );
+        assert_eq!(status, Status::Ok);
+
+        response
+    }
+
+    fn refresh(client: &Client, response: &Value, scope: Option<&str>) -> (Status, Value) {
+        let scope = scope.map(|scope| format!("&scope={}", scope));
+        token(
+            client,
+            format!(
+                "grant_type=refresh_token&refresh_token={}{}",
+                response["refresh_token"].as_str().unwrap(),
+                scope.unwrap_or_default()
+            ),
+        )
+    }
+
+    #[test]
+    fn refresh_within_the_scopes_of_the_session() {
+        let client = client(rs256_settings());
+
+        // a session without scopes never had the openid one
+        let response = login(&client, None);
+        let (status, refreshed) = refresh(&client, &response, None);
+        assert_eq!(status, Status::Ok);
+        assert!(refreshed.get("id_token").is_none());
+        assert!(!refreshed["scope"].as_str().unwrap().contains("openid"));
+        let (status, error) = refresh(&client, &refreshed, Some("openid"));
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "invalid_scope");
+
+        // nor can a restricted session gain scopes
+        let response = login(&client, Some("account%20openid"));
+        assert!(response["id_token"].is_string());
+        let (status, error) = refresh(&client, &response, Some("account%20users:read"));
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "invalid_scope");
+
+        let response = login(&client, Some("account%20openid"));
+        let (status, refreshed) = refresh(&client, &response, Some("openid"));
+        assert_eq!(status, Status::Ok);
+        assert_eq!(refreshed["scope"], "account openid");
+        assert!(refreshed["id_token"].is_string());
+    }
+
+    #[test]
+    fn refuse_the_grant_of_an_ended_session() {
+        let settings = rs256_settings();
+        let database_url = settings.app.clone().unwrap_or_default().db_url;
+        let client = client(settings);
+
+        let response = login(&client, None);
+        SqliteConnection::establish(&database_url)
+            .unwrap()
+            .batch_execute("UPDATE sessions SET expires_at = 0")
+            .unwrap();
+
+        // the grant is invalid, the client itself being fine
+        let (status, error) = refresh(&client, &response, None);
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "invalid_grant");
+    }
+}
@@ -1,43 +1,212 @@
 use crate::{
-    backends::Backend,
-    db::model::{ApiKey, NewPassword, PartialUser, User, UserCredentials},
+    backends::{now, Backend},
+    db::model::{
+        ActionToken, ApiKey, EmailVerification, IssuedPersonalAccessToken, NewActionToken,
+        NewPassword, NewPersonalAccessToken, PartialPersonalAccessToken, PartialUser,
+        PasswordReset, PersonalAccessToken, RefreshRequest, RefreshToken, RevokedToken, Session,
+        UsedActionToken, User, UserCredentials,
+    },
     error::Error,
-    secure::tokenizer::{hash, Tokenizer},
+    secure::{
+        password::PasswordHasher,
+        password_policy::PasswordPolicy,
+        tokenizer::{
+            random_id, ActionPurpose, TokenClaims, Tokenizer, MAX_PERSONAL_ACCESS_TOKEN_LIFETIME,
+            PERSONAL_ACCESS_TOKEN_PREFIX, SCOPES, SCOPE_ACCOUNT, SCOPE_USERS_READ,
+            SCOPE_USERS_WRITE,
+        },
+    },
+    server::{
+        config::SessionCookieConfig,
+        guards::{client::ClientInfo, cookie::RefreshCookie},
+    },
     Result,
 };
 
 use rocket::{
+    http::CookieJar,
     response::status::Created,
     serde::json::{self, Json},
     State,
 };
 
+/// Issue an access token along with a refresh token for the given login session.
+///
+/// A new session, restricted to the given scopes if any, is started when the client
+/// information is given; the existing one is moved to the new tokens otherwise, and
+/// keeps its own scopes.
+pub(crate) fn issue_tokens(
+    user: User,
+    session_id: String,
+    client: Option<ClientInfo>,
+    scope: Option<String>,
+    tokenizer: &Tokenizer,
+    backend: &Backend,
+) -> Result<ApiKey> {
+    let (auth_time, scope) = match client {
+        Some(_) => (now(), scope),
+        None => {
+            let session = backend.get_session(&session_id)?;
+            (session.created_at, session.scope)
+        }
+    };
+    let token = tokenizer.generate(&user, &session_id, auth_time as u64, scope.as_deref())?;
+    let (refresh_token, expires_at) = tokenizer.generate_refresh_token();
+    let expires_at = expires_at as i64;
+
+    match client {
+        Some(client) => backend.add_session(Session {
+            id: session_id.clone(),
+            username: user.username.clone(),
+            token_hash: tokenizer.digest(&token),
+            created_at: auth_time,
+            expires_at,
+            user_agent: client.user_agent,
+            ip_address: client.ip_address,
+            previous_token_hash: None,
+            previous_token_expires_at: None,
+            scope: scope.clone(),
+        })?,
+        None => backend.update_session_token(&session_id, &tokenizer.digest(&token), expires_at)?,
+    }
+    backend.add_refresh_token(RefreshToken {
+        token_hash: tokenizer.digest(&refresh_token),
+        family_id: session_id,
+        username: user.username,
+        expires_at,
+        used: false,
+    })?;
+
+    Ok(ApiKey {
+        token,
+        refresh_token: Some(refresh_token),
+        csrf_token: None,
+        claims: TokenClaims {
+            scopes: scope.map(|scope| scope.split_whitespace().map(String::from).collect()),
+            ..Default::default()
+        },
+    })
+}
+
+/// Move the tokens into the session cookies when asked to, out of the reach of scripts
+fn deliver_tokens(
+    api_key: ApiKey,
+    cookie: Option<bool>,
+    session_cookie: &Option<SessionCookieConfig>,
+    cookies: &CookieJar<'_>,
+) -> Result<ApiKey> {
+    match (cookie.unwrap_or_default(), session_cookie) {
+        (false, _) => Ok(api_key),
+        (true, Some(session_cookie)) => Ok(ApiKey {
+            csrf_token: Some(session_cookie.add_session(
+                cookies,
+                &api_key.token,
+                api_key.refresh_token.as_deref(),
+            )),
+            token: String::new(),
+            refresh_token: None,
+            ..api_key
+        }),
+        (true, None) => Err(Error::BadRequest(
+            "Cookie sessions are not enabled".to_string(),
+        )),
+    }
+}
+
 #[openapi(tag = "Users")]
-#[post("/auth", data = "<credentials>")]
+#[post("/auth?<cookie>", data = "<credentials>")]
+#[allow(clippy::too_many_arguments)]
 pub fn authenticate_user(
     credentials: std::result::Result<Json<UserCredentials>, json::Error<'_>>,
+    cookie: Option<bool>,
+    client: ClientInfo,
+    session_cookie: &State<Option<SessionCookieConfig>>,
+    cookies: &CookieJar<'_>,
     tokenizer: &State<Tokenizer>,
+    hasher: &State<PasswordHasher>,
     backend: &State<Backend>,
 ) -> Result<Json<ApiKey>> {
     let credentials = credentials?;
 
     backend
-        .find_user(UserCredentials {
-            password: hash(&credentials.password),
-            ..credentials.into_inner()
-        })
+        .find_user(credentials.into_inner(), hasher)
+        .and_then(|user| issue_tokens(user, random_id(16), Some(client), None, tokenizer, backend))
+        .and_then(|api_key| deliver_tokens(api_key, cookie, session_cookie, cookies))
+        .map(Json)
+}
+
+#[openapi(tag = "Users")]
+#[post("/refresh?<cookie>", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+pub fn refresh_token(
+    request: std::result::Result<Json<RefreshRequest>, json::Error<'_>>,
+    refresh_cookie: std::result::Result<RefreshCookie, Error>,
+    cookie: Option<bool>,
+    session_cookie: &State<Option<SessionCookieConfig>>,
+    cookies: &CookieJar<'_>,
+    tokenizer: &State<Tokenizer>,
+    backend: &State<Backend>,
+) -> Result<Json<ApiKey>> {
+    // browser clients hold the refresh token in its cookie, not in the body
+    let refresh_token = match (request, cookie.unwrap_or_default()) {
+        (Ok(request), _) => request.into_inner().refresh_token,
+        (Err(e), true) => match refresh_cookie? {
+            RefreshCookie(Some(refresh_token)) => refresh_token,
+            RefreshCookie(None) => return Err(e.into()),
+        },
+        (Err(e), false) => return Err(e.into()),
+    };
+
+    // rotate: the presented refresh token can never be used again
+    let refresh_token = backend.use_refresh_token(&tokenizer.digest(&refresh_token))?;
+
+    backend
+        .get_user(&refresh_token.username)
         .and_then(|user| {
-            tokenizer.generate().map(|token| User {
-                token: Some(token),
-                ..user
-            })
-        })
-        .and_then(|user| backend.update_user(user))
-        .map(|user| {
-            Json(ApiKey {
-                token: user.token.unwrap(),
-            })
+            issue_tokens(
+                user,
+                refresh_token.family_id,
+                None,
+                None,
+                tokenizer,
+                backend,
+            )
         })
+        .and_then(|api_key| deliver_tokens(api_key, cookie, session_cookie, cookies))
+        .map(Json)
+}
+
+#[openapi(tag = "Users")]
+#[post("/logout")]
+pub fn logout(
+    api_key: std::result::Result<ApiKey, Error>,
+    session_cookie: &State<Option<SessionCookieConfig>>,
+    cookies: &CookieJar<'_>,
+    backend: &State<Backend>,
+) -> Result<()> {
+    let api_key = api_key?;
+    let claims = api_key.claims;
+
+    if let Some(session_cookie) = session_cookie.inner() {
+        session_cookie.remove_session(cookies);
+    }
+
+    // a personal access token used to log out is revoked for good
+    if api_key.token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX) {
+        return backend.delete_personal_access_token(&claims.subject, &claims.token_id);
+    }
+
+    // only the personal access tokens never expire
+    if let Some(expires_at) = claims.expires_at {
+        backend.revoke_token(RevokedToken {
+            jti: claims.token_id,
+            expires_at: expires_at as i64,
+        })?;
+    }
+    match claims.session_id {
+        Some(session_id) => backend.end_session(&session_id),
+        None => Ok(()),
+    }
 }
 
 #[openapi(tag = "Users")]
@@ -46,7 +215,7 @@ pub fn get_all_users(
     backend: &State<Backend>,
     api_key: std::result::Result<ApiKey, Error>,
 ) -> Result<Json<Vec<PartialUser>>> {
-    let _ = api_key?;
+    let _ = api_key?.require_admin()?.require_scope(SCOPE_USERS_READ)?;
 
     Ok(Json(
         backend
@@ -62,16 +231,21 @@ pub fn get_all_users(
 pub fn add_user(
     user: std::result::Result<Json<User>, json::Error<'_>>,
     api_key: std::result::Result<ApiKey, Error>,
+    hasher: &State<PasswordHasher>,
+    policy: &State<PasswordPolicy>,
     backend: &State<Backend>,
 ) -> Result<Created<()>> {
     let user = user?;
-    let _ = api_key?;
+    let _ = api_key?.require_admin()?.require_scope(SCOPE_USERS_WRITE)?;
+    policy.check("password", &user.password, &user.username, &user.email)?;
 
     let username = &user.username.clone();
 
     backend
         .add_user(User {
-            password: hash(&user.password),
+            password: hasher.hash(&user.password)?,
+            // only the user can prove owning the email address
+            email_verified: false,
             ..user.into_inner()
         })
         .map(|_| Created::new(format!("/user/{}", username)))
@@ -82,17 +256,23 @@ pub fn add_user(
 pub fn change_user_password(
     password: std::result::Result<Json<NewPassword>, json::Error<'_>>,
     api_key: std::result::Result<ApiKey, Error>,
+    hasher: &State<PasswordHasher>,
+    policy: &State<PasswordPolicy>,
     backend: &State<Backend>,
 ) -> Result<()> {
-    let api_key = api_key?;
+    // every user changes their own password, proving they know the current one
+    let api_key = api_key?
+        .require_scope(SCOPE_ACCOUNT)?
+        .require_not_impersonated()?;
     let password = password?;
 
     backend
-        .find_user_by_token(&api_key.token)
+        .get_user(&api_key.claims.subject)
         .and_then(|user| {
-            if user.password == hash(&password.current) {
+            if hasher.verify(&password.current, &user.password) {
+                policy.check("new", &password.new, &user.username, &user.email)?;
                 Ok(User {
-                    password: hash(&password.new),
+                    password: hasher.hash(&password.new)?,
                     ..user
                 })
             } else {
@@ -103,6 +283,89 @@ pub fn change_user_password(
         .map(|_| ())
 }
 
+#[openapi(tag = "Users")]
+#[post("/<username>/action_token", data = "<request>")]
+pub fn add_action_token(
+    username: String,
+    request: std::result::Result<Json<NewActionToken>, json::Error<'_>>,
+    api_key: std::result::Result<ApiKey, Error>,
+    tokenizer: &State<Tokenizer>,
+    backend: &State<Backend>,
+) -> Result<Json<ActionToken>> {
+    let _ = api_key?
+        .require_admin()?
+        .require_scope(SCOPE_USERS_WRITE)?
+        .require_not_impersonated()?;
+    let purpose = request?.purpose;
+
+    let user = backend.get_user(&username)?;
+    let (token, expires_at) = tokenizer.generate_action_token(&user, purpose)?;
+
+    Ok(Json(ActionToken {
+        token,
+        purpose,
+        expires_at,
+    }))
+}
+
+#[openapi(tag = "Users")]
+#[post("/reset_password", data = "<request>")]
+pub fn reset_user_password(
+    request: std::result::Result<Json<PasswordReset>, json::Error<'_>>,
+    tokenizer: &State<Tokenizer>,
+    hasher: &State<PasswordHasher>,
+    policy: &State<PasswordPolicy>,
+    backend: &State<Backend>,
+) -> Result<()> {
+    let request = request?;
+    let action = tokenizer.verify_action_token(&request.token, ActionPurpose::PasswordReset)?;
+
+    let user = backend.get_user(&action.subject)?;
+    // checked before the token is used up, so that another password can be tried
+    policy.check("password", &request.password, &user.username, &user.email)?;
+    backend.use_action_token(UsedActionToken {
+        nonce: action.nonce,
+        expires_at: action.expires_at as i64,
+    })?;
+    backend.update_user(User {
+        password: hasher.hash(&request.password)?,
+        ..user
+    })?;
+
+    // whoever knew the former password is logged out, and loses the tokens they created
+    backend.end_user_sessions(&action.subject)?;
+    backend.delete_user_personal_access_tokens(&action.subject)
+}
+
+#[openapi(tag = "Users")]
+#[post("/verify_email", data = "<request>")]
+pub fn verify_user_email(
+    request: std::result::Result<Json<EmailVerification>, json::Error<'_>>,
+    tokenizer: &State<Tokenizer>,
+    backend: &State<Backend>,
+) -> Result<()> {
+    let request = request?;
+    let action = tokenizer.verify_action_token(&request.token, ActionPurpose::EmailVerification)?;
+
+    let user = backend.get_user(&action.subject)?;
+    if action.email.as_deref() != Some(user.email.as_str()) {
+        return Err(Error::BadRequest(
+            "Token was issued for another email address".to_string(),
+        ));
+    }
+    backend.use_action_token(UsedActionToken {
+        nonce: action.nonce,
+        expires_at: action.expires_at as i64,
+    })?;
+
+    backend
+        .update_user(User {
+            email_verified: true,
+            ..user
+        })
+        .map(|_| ())
+}
+
 #[openapi(tag = "Users")]
 #[delete("/<username>")]
 pub fn delete_user(
@@ -110,7 +373,418 @@ pub fn delete_user(
     api_key: std::result::Result<ApiKey, Error>,
     backend: &State<Backend>,
 ) -> Result<()> {
-    let _ = api_key?;
+    let _ = api_key?.require_admin()?.require_scope(SCOPE_USERS_WRITE)?;
 
     backend.delete_user(&username)
 }
+
+#[openapi(tag = "Users")]
+#[post("/<username>/impersonate")]
+pub fn impersonate_user(
+    username: String,
+    api_key: std::result::Result<ApiKey, Error>,
+    client: ClientInfo,
+    tokenizer: &State<Tokenizer>,
+    backend: &State<Backend>,
+) -> Result<Json<ApiKey>> {
+    let api_key = api_key?
+        .require_admin()?
+        .require_scope(SCOPE_USERS_WRITE)?
+        .require_not_impersonated()?;
+    // only admin users impersonate, not the clients
+    if api_key.claims.client_id.is_some() {
+        return Err(Error::ForbiddenAccess);
+    }
+    if api_key.claims.subject == username {
+        return Err(Error::BadRequest(
+            "Admins cannot impersonate themselves".to_string(),
+        ));
+    }
+
+    let user = backend.get_user(&username)?;
+    // impersonating is for reproducing the issues of users, not for gaining other admin rights
+    if user.is_admin {
+        return Err(Error::ForbiddenAccess);
+    }
+
+    let session_id = random_id(16);
+    let (token, expires_at) =
+        tokenizer.generate_impersonation_token(&user, &session_id, &api_key.claims.subject)?;
+    backend.add_session(Session {
+        id: session_id,
+        username: user.username,
+        token_hash: tokenizer.digest(&token),
+        created_at: now(),
+        expires_at: expires_at as i64,
+        user_agent: client.user_agent,
+        ip_address: client.ip_address,
+        previous_token_hash: None,
+        previous_token_expires_at: None,
+        scope: None,
+    })?;
+
+    Ok(Json(ApiKey {
+        token,
+        refresh_token: None,
+        csrf_token: None,
+        claims: Default::default(),
+    }))
+}
+
+#[openapi(tag = "Tokens")]
+#[post("/tokens", data = "<request>")]
+pub fn add_personal_access_token(
+    request: std::result::Result<Json<NewPersonalAccessToken>, json::Error<'_>>,
+    api_key: std::result::Result<ApiKey, Error>,
+    tokenizer: &State<Tokenizer>,
+    backend: &State<Backend>,
+) -> Result<Created<Json<IssuedPersonalAccessToken>>> {
+    let api_key = api_key?
+        .require_scope(SCOPE_ACCOUNT)?
+        .require_not_impersonated()?;
+    let request = request?.into_inner();
+
+    let name = request.name.trim();
+    if name.is_empty() || name.len() > 255 {
+        return Err(Error::BadRequest(
+            "Token name must be between 1 and 255 characters long".to_string(),
+        ));
+    }
+    if request.scopes.is_empty() {
+        return Err(Error::BadRequest(
+            "At least one scope is required".to_string(),
+        ));
+    }
+    if let Some(scope) = request
+        .scopes
+        .iter()
+        .find(|scope| !SCOPES.contains(&scope.as_str()))
+    {
+        return Err(Error::BadRequest(format!("Unknown scope: {}", scope)));
+    }
+    // a token cannot be granted more than its creator has
+    if let Some(granted) = &api_key.claims.scopes {
+        if request.scopes.iter().any(|scope| !granted.contains(scope)) {
+            return Err(Error::ForbiddenAccess);
+        }
+    }
+    let created_at = now();
+    let expires_at = match &request.expires_in {
+        Some(expires_in) => {
+            let expires_in = parse_duration::parse(expires_in)
+                .map_err(|e| Error::BadRequest(format!("Invalid expires_in: {}", e)))?
+                .as_secs();
+
+            // bounded, so that the expiry time cannot overflow either
+            Some(
+                i64::try_from(expires_in)
+                    .ok()
+                    .filter(|_| expires_in <= MAX_PERSONAL_ACCESS_TOKEN_LIFETIME)
+                    .and_then(|expires_in| created_at.checked_add(expires_in))
+                    .ok_or_else(|| {
+                        Error::BadRequest(format!(
+                            "expires_in must not exceed {} days",
+                            MAX_PERSONAL_ACCESS_TOKEN_LIFETIME / (24 * 60 * 60)
+                        ))
+                    })?,
+            )
+        }
+        None => None,
+    };
+
+    let mut scopes = request.scopes;
+    scopes.sort();
+    scopes.dedup();
+    let token = format!("{}{}", PERSONAL_ACCESS_TOKEN_PREFIX, random_id(32));
+    let personal_access_token = PersonalAccessToken {
+        id: random_id(16),
+        username: api_key.claims.subject,
+        name: name.to_string(),
+        token_hash: tokenizer.digest(&token),
+        scopes: scopes.join(" "),
+        created_at,
+        expires_at,
+    };
+    backend.add_personal_access_token(personal_access_token.clone())?;
+
+    let location = format!("/user/tokens/{}", personal_access_token.id);
+    Ok(Created::new(location).body(Json(IssuedPersonalAccessToken {
+        info: personal_access_token.into(),
+        token,
+    })))
+}
+
+#[openapi(tag = "Tokens")]
+#[get("/tokens")]
+pub fn get_personal_access_tokens(
+    api_key: std::result::Result<ApiKey, Error>,
+    backend: &State<Backend>,
+) -> Result<Json<Vec<PartialPersonalAccessToken>>> {
+    let api_key = api_key?.require_scope(SCOPE_ACCOUNT)?;
+
+    Ok(Json(
+        backend
+            .list_personal_access_tokens(&api_key.claims.subject)?
+            .into_iter()
+            .map(PartialPersonalAccessToken::from)
+            .collect(),
+    ))
+}
+
+#[openapi(tag = "Tokens")]
+#[delete("/tokens/<id>")]
+pub fn delete_personal_access_token(
+    id: String,
+    api_key: std::result::Result<ApiKey, Error>,
+    backend: &State<Backend>,
+) -> Result<()> {
+    let api_key = api_key?.require_scope(SCOPE_ACCOUNT)?;
+
+    backend.delete_personal_access_token(&api_key.claims.subject, &id)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::server::{
+        config::{SlidingExpirationConfig, VerificationMode},
+        testing::{backend, client, login, settings, tokens, PASSWORD},
+    };
+
+    use rocket::http::{ContentType, Header, Method, Status};
+
+    #[test]
+    fn refresh_through_the_cookie() {
+        let client = client(settings());
+
+        let response = login(&client, "user", true);
+        let refresh_cookie = response.cookies().get("rocketjwt_refresh").unwrap();
+        // only sent to the refresh route
+        assert_eq!(refresh_cookie.path(), Some("/user/refresh"));
+        assert_eq!(refresh_cookie.http_only(), Some(true));
+        let api_key = response.into_json::<ApiKey>().unwrap();
+        assert!(api_key.token.is_empty());
+        assert!(api_key.refresh_token.is_none());
+        let csrf_token = api_key.csrf_token.unwrap();
+
+        let response = client
+            .post("/user/refresh?cookie=true")
+            .header(Header::new("X-CSRF-Token", csrf_token))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let refresh_cookie = response.cookies().get("rocketjwt_refresh").unwrap();
+        assert_eq!(refresh_cookie.path(), Some("/user/refresh"));
+        let csrf_token = response.into_json::<ApiKey>().unwrap().csrf_token.unwrap();
+
+        // the refresh cookie is removed on logout, from its own path
+        let response = client
+            .post("/user/logout")
+            .header(Header::new("X-CSRF-Token", csrf_token.clone()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let refresh_cookie = response.cookies().get("rocketjwt_refresh").unwrap();
+        assert_eq!(refresh_cookie.path(), Some("/user/refresh"));
+        assert_eq!(refresh_cookie.value(), "");
+
+        let response = client
+            .post("/user/refresh?cookie=true")
+            .header(Header::new("X-CSRF-Token", csrf_token))
+            .dispatch();
+        assert_ne!(response.status(), Status::Ok);
+    }
+
+    #[test]
+    fn reject_non_admins_on_admin_routes() {
+        let client = client(settings());
+        let bearer = |username| {
+            Header::new(
+                "Authorization",
+                format!("Bearer {}", tokens(&client, username).token),
+            )
+        };
+        let routes = [
+            (Method::Get, "/user/", None),
+            (
+                Method::Post,
+                "/user/",
+                Some(
+                    r#"{"username": "eve", "email": "eve@example.com", "password": "Tr0ub4dor&3", "is_admin": true}"#,
+                ),
+            ),
+            (Method::Delete, "/user/admin", None),
+            (Method::Post, "/user/admin/impersonate", None),
+            (
+                Method::Post,
+                "/user/admin/action_token",
+                Some(r#"{"purpose": "password_reset"}"#),
+            ),
+            (
+                Method::Post,
+                "/oauth/clients",
+                Some(r#"{"client_id": "eve", "scopes": ["users:read"]}"#),
+            ),
+            (Method::Get, "/oauth/clients", None),
+            (Method::Delete, "/oauth/clients/eve", None),
+        ];
+
+        let user = bearer("user");
+        for (method, uri, body) in &routes {
+            let request = client
+                .req(*method, *uri)
+                .header(user.clone())
+                .header(ContentType::JSON);
+            let response = match body {
+                Some(body) => request.body(body).dispatch(),
+                None => request.dispatch(),
+            };
+            assert_eq!(response.status(), Status::Forbidden, "{} {}", method, uri);
+        }
+        assert!(backend(&client).get_user("eve").is_err());
+        assert!(backend(&client).get_user("admin").is_ok());
+
+        // while admins are let through
+        let response = client.get("/user/").header(bearer("admin")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[test]
+    fn do_not_renew_the_token_on_logout() {
+        let mut settings = settings();
+        settings.server.jwt_sliding_expiration = Some(SlidingExpirationConfig {
+            renew_after: 0.0,
+            max_session_age: "1h".to_string(),
+        });
+        settings.server.jwt_verification = VerificationMode::RevocationList;
+        let client = client(settings);
+        let bearer = |token: &str| Header::new("Authorization", format!("Bearer {}", token));
+        let token = tokens(&client, "user").token;
+
+        // tokens in use are renewed
+        let response = client.get("/user/tokens").header(bearer(&token)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let renewed = response.headers().get_one("X-Renewed-Token").unwrap();
+
+        // but not the one being logged out
+        let response = client
+            .post("/user/logout")
+            .header(bearer(renewed))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("X-Renewed-Token").is_none());
+
+        let response = client
+            .get("/user/tokens")
+            .header(bearer(renewed))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn use_personal_access_tokens() {
+        let client = client(settings());
+        let bearer = |token: &str| Header::new("Authorization", format!("Bearer {}", token));
+        let admin = tokens(&client, "admin").token;
+        let add_token = |expires_in: Option<&str>| {
+            client
+                .post("/user/tokens")
+                .header(bearer(&admin))
+                .header(ContentType::JSON)
+                .body(
+                    json::json!({ "name": "ci", "scopes": ["account"], "expires_in": expires_in })
+                        .to_string(),
+                )
+                .dispatch()
+        };
+        let introspect = |token: &str| {
+            client
+                .post("/oauth/introspect")
+                .header(bearer(&admin))
+                .header(ContentType::Form)
+                .body(format!("token={}", token))
+                .dispatch()
+                .into_json::<json::Value>()
+                .unwrap()
+        };
+
+        // the lifetime of the tokens set to expire is bounded
+        let days = MAX_PERSONAL_ACCESS_TOKEN_LIFETIME / (24 * 60 * 60);
+        let response = add_token(Some(&format!("{} days", days + 1)));
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let response = add_token(Some(&format!("{} days", days)));
+        assert_eq!(response.status(), Status::Created);
+        let expiring = response.into_json::<IssuedPersonalAccessToken>().unwrap();
+        let expires_at = expiring.info.created_at + MAX_PERSONAL_ACCESS_TOKEN_LIFETIME as i64;
+        assert_eq!(expiring.info.expires_at, Some(expires_at));
+        assert_eq!(introspect(&expiring.token)["exp"], expires_at);
+
+        // while the others never expire, and have no expiry time
+        let response = add_token(None);
+        assert_eq!(response.status(), Status::Created);
+        let endless = response.into_json::<IssuedPersonalAccessToken>().unwrap();
+        assert_eq!(endless.info.expires_at, None);
+        let introspection = introspect(&endless.token);
+        assert_eq!(introspection["active"], true);
+        assert_eq!(introspection["sub"], "admin");
+        assert!(introspection.get("exp").is_none());
+
+        // the tokens are looked up by the bearer guard
+        let response = client
+            .get("/user/tokens")
+            .header(bearer(&endless.token))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let listed = response
+            .into_json::<Vec<PartialPersonalAccessToken>>()
+            .unwrap();
+        assert_eq!(listed.len(), 2);
+
+        let response = client
+            .delete(format!("/user/tokens/{}", endless.info.id))
+            .header(bearer(&admin))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get("/user/tokens")
+            .header(bearer(&endless.token))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(introspect(&endless.token)["active"], false);
+    }
+
+    #[test]
+    fn change_the_own_password() {
+        let client = client(settings());
+        let token = tokens(&client, "user").token;
+        let change_password = |current: &str, new: &str| {
+            client
+                .post("/user/change_password")
+                .header(Header::new("Authorization", format!("Bearer {}", token)))
+                .header(ContentType::JSON)
+                .body(json::json!({ "current": current, "new": new }).to_string())
+                .dispatch()
+                .status()
+        };
+        let authenticate = |password: &str| {
+            client
+                .post("/user/auth")
+                .header(ContentType::JSON)
+                .body(json::json!({ "username": "user", "password": password }).to_string())
+                .dispatch()
+                .status()
+        };
+
+        assert_eq!(
+            change_password("not the password", "c0rrect-h0rse-battery"),
+            Status::BadRequest
+        );
+        assert_eq!(authenticate(PASSWORD), Status::Ok);
+
+        assert_eq!(
+            change_password(PASSWORD, "c0rrect-h0rse-battery"),
+            Status::Ok
+        );
+        assert_ne!(authenticate(PASSWORD), Status::Ok);
+        assert_eq!(authenticate("c0rrect-h0rse-battery"), Status::Ok);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{
+    error::Error,
+    secure::{
+        keys::{Algorithm, Jwks},
+        tokenizer::{Tokenizer, OPENID_SCOPES, SCOPES},
+    },
+    server::config::PublicUrl,
+    Result,
+};
+
+use rocket::{http::Header, serde::json::Json, State};
+use serde::Serialize;
+
+/// How long clients may cache the published keys, in seconds
+const JWKS_MAX_AGE: u32 = 900;
+
+#[derive(Responder)]
+pub struct JwksResponse {
+    inner: Json<Jwks>,
+    cache_control: Header<'static>,
+}
+
+/// OpenID Connect discovery document.
+///
+/// There is no authorization endpoint, as none of the supported grants goes through one
+/// (RFC 8414, section 2): the only response type is `none`, the list being required.
+#[derive(Serialize)]
+pub struct OpenIdConfiguration {
+    issuer: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    introspection_endpoint: String,
+    jwks_uri: String,
+    scopes_supported: Vec<&'static str>,
+    response_types_supported: Vec<&'static str>,
+    grant_types_supported: Vec<&'static str>,
+    subject_types_supported: Vec<&'static str>,
+    id_token_signing_alg_values_supported: Vec<Algorithm>,
+    token_endpoint_auth_methods_supported: Vec<&'static str>,
+    claims_supported: Vec<&'static str>,
+}
+
+#[get("/jwks.json")]
+pub fn jwks(tokenizer: &State<Tokenizer>) -> JwksResponse {
+    JwksResponse {
+        inner: Json(tokenizer.jwks()),
+        cache_control: Header::new("Cache-Control", format!("public, max-age={}", JWKS_MAX_AGE)),
+    }
+}
+
+/// Only served with a public URL configured, which the request cannot be trusted for and
+/// the tokens are issued by, and with ID tokens the clients can verify
+#[get("/openid-configuration")]
+pub fn openid_configuration(
+    tokenizer: &State<Tokenizer>,
+    public_url: &State<PublicUrl>,
+) -> Result<Json<OpenIdConfiguration>> {
+    let base_url = match &public_url.0 {
+        Some(public_url) if tokenizer.issues_id_tokens() => public_url.trim_end_matches('/'),
+        _ => return Err(Error::NotFound),
+    };
+
+    Ok(Json(OpenIdConfiguration {
+        issuer: tokenizer.issuer().to_string(),
+        token_endpoint: format!("{}/oauth/token", base_url),
+        userinfo_endpoint: format!("{}/oauth/userinfo", base_url),
+        introspection_endpoint: format!("{}/oauth/introspect", base_url),
+        jwks_uri: format!("{}/.well-known/jwks.json", base_url),
+        scopes_supported: OPENID_SCOPES.into_iter().chain(SCOPES).collect(),
+        response_types_supported: vec!["none"],
+        grant_types_supported: vec!["password", "refresh_token", "client_credentials"],
+        subject_types_supported: vec!["public"],
+        id_token_signing_alg_values_supported: vec![tokenizer.signing_algorithm()],
+        token_endpoint_auth_methods_supported: vec!["client_secret_basic", "client_secret_post"],
+        claims_supported: vec![
+            "sub",
+            "iss",
+            "aud",
+            "exp",
+            "iat",
+            "auth_time",
+            "sid",
+            "email",
+            "email_verified",
+        ],
+    }))
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use crate::{
+        error::Error,
+        server::{
+            build_server,
+            testing::{client, rs256_settings, settings},
+        },
+    };
+
+    use rocket::{
+        http::Status,
+        serde::json::{serde_json, Value},
+    };
+
+    #[test]
+    fn serve_the_discovery_document() {
+        let mut settings = rs256_settings();
+        settings.server.public_url = Some("https://auth.example.com/".to_string());
+        settings.server.jwt_issuer = "https://auth.example.com".to_string();
+        let client = client(settings);
+
+        let response = client.get("/.well-known/openid-configuration").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let configuration = response.into_json::<Value>().unwrap();
+        assert_eq!(configuration["issuer"], "https://auth.example.com");
+        assert_eq!(
+            configuration["token_endpoint"],
+            "https://auth.example.com/oauth/token"
+        );
+        assert_eq!(
+            configuration["response_types_supported"],
+            serde_json::json!(["none"])
+        );
+        assert_eq!(
+            configuration["id_token_signing_alg_values_supported"],
+            serde_json::json!(["RS256"])
+        );
+    }
+
+    #[test]
+    fn refuse_a_public_url_other_than_the_issuer() {
+        let mut mismatched = settings();
+        mismatched.server.public_url = Some("https://auth.example.com".to_string());
+        assert!(matches!(
+            build_server(mismatched),
+            Err(Error::IssuerMismatch)
+        ));
+
+        // nor is the document served without ID tokens
+        let mut without_id_tokens = settings();
+        without_id_tokens.server.public_url = Some("https://auth.example.com".to_string());
+        without_id_tokens.server.jwt_issuer = "https://auth.example.com".to_string();
+        let client = client(without_id_tokens);
+        let response = client.get("/.well-known/openid-configuration").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
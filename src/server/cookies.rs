@@ -0,0 +1,184 @@
+use crate::{error::Error, secure::tokenizer::random_id, server::config::SessionCookieConfig};
+
+use rocket::{
+    http::{Cookie, CookieJar, Method},
+    Request,
+};
+use subtle::ConstantTimeEq;
+
+/// Path of the refresh token cookie: the only route it is of use to
+const REFRESH_COOKIE_PATH: &str = "/user/refresh";
+
+impl SessionCookieConfig {
+    /// Build a cookie with the configured attributes
+    fn cookie(&self, name: &str, value: String, http_only: bool) -> Cookie<'static> {
+        Cookie::build((name.to_string(), value))
+            .path("/")
+            .http_only(http_only)
+            .secure(self.secure)
+            .same_site(self.same_site.into())
+            .build()
+    }
+
+    /// Build the refresh token cookie
+    fn refresh_cookie(&self, value: String) -> Cookie<'static> {
+        let mut cookie = self.cookie(&self.refresh_cookie_name, value, true);
+        cookie.set_path(REFRESH_COOKIE_PATH);
+
+        cookie
+    }
+
+    /// Deliver the access token in the private session cookie, and the refresh token in
+    /// its own, along with a fresh CSRF token; the CSRF token is returned for the front-end
+    /// to echo
+    pub(crate) fn add_session(
+        &self,
+        cookies: &CookieJar<'_>,
+        token: &str,
+        refresh_token: Option<&str>,
+    ) -> String {
+        let csrf_token = random_id(32);
+
+        cookies.add_private(self.cookie(&self.name, token.to_string(), true));
+        if let Some(refresh_token) = refresh_token {
+            cookies.add_private(self.refresh_cookie(refresh_token.to_string()));
+        }
+        cookies.add(self.cookie(&self.csrf_cookie_name, csrf_token.clone(), false));
+
+        csrf_token
+    }
+
+    /// Replace the access token of the session cookie, keeping the CSRF token
+    pub(crate) fn renew_session(&self, cookies: &CookieJar<'_>, token: &str) {
+        cookies.add_private(self.cookie(&self.name, token.to_string(), true));
+    }
+
+    /// Clear the session cookies
+    pub(crate) fn remove_session(&self, cookies: &CookieJar<'_>) {
+        cookies.remove_private(self.cookie(&self.name, String::new(), true));
+        // not sent outside of its path, so not in the jar to be removed from
+        let mut refresh_cookie = self.refresh_cookie(String::new());
+        refresh_cookie.make_removal();
+        cookies.add(refresh_cookie);
+        cookies.remove(self.cookie(&self.csrf_cookie_name, String::new(), false));
+    }
+
+    /// Access token of the session cookie, if any.
+    ///
+    /// State-changing requests must carry the CSRF token of the session in the
+    /// configured header (double-submit), as a cross-site form cannot read it.
+    pub(crate) fn session_token(&self, request: &Request<'_>) -> Result<Option<String>, Error> {
+        let token = match request.cookies().get_private(&self.name) {
+            Some(cookie) => cookie.value().to_string(),
+            None => return Ok(None),
+        };
+
+        if !matches!(
+            request.method(),
+            Method::Get | Method::Head | Method::Options
+        ) {
+            self.check_csrf(request)?;
+        }
+
+        Ok(Some(token))
+    }
+
+    /// Refresh token of the refresh cookie, if any; always checked against the CSRF token
+    pub(crate) fn refresh_token(&self, request: &Request<'_>) -> Result<Option<String>, Error> {
+        let refresh_token = match request.cookies().get_private(&self.refresh_cookie_name) {
+            Some(cookie) => cookie.value().to_string(),
+            None => return Ok(None),
+        };
+        self.check_csrf(request)?;
+
+        Ok(Some(refresh_token))
+    }
+
+    /// Make sure the CSRF header echoes the CSRF cookie
+    fn check_csrf(&self, request: &Request<'_>) -> Result<(), Error> {
+        let csrf_cookie = request
+            .cookies()
+            .get(&self.csrf_cookie_name)
+            .map(|cookie| cookie.value().to_string());
+        let csrf_header = request.headers().get_one(&self.csrf_header_name);
+
+        match (csrf_cookie, csrf_header) {
+            // constant time comparison
+            (Some(cookie), Some(header))
+                if !cookie.is_empty() && bool::from(cookie.as_bytes().ct_eq(header.as_bytes())) =>
+            {
+                Ok(())
+            }
+            _ => Err(Error::ForbiddenAccess),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use crate::{
+        db::model::ApiKey,
+        server::testing::{client, login, settings},
+    };
+
+    use rocket::http::{ContentType, Header, Status};
+
+    #[test]
+    fn require_the_csrf_token_of_the_session() {
+        let client = client(settings());
+        let csrf_token = login(&client, "user", true)
+            .into_json::<ApiKey>()
+            .unwrap()
+            .csrf_token
+            .unwrap();
+        let add_token = |csrf_header: Option<&str>| {
+            let request = client
+                .post("/user/tokens")
+                .header(ContentType::JSON)
+                .body(r#"{"name": "ci", "scopes": ["account"]}"#);
+            match csrf_header {
+                Some(csrf_header) => {
+                    request.header(Header::new("X-CSRF-Token", csrf_header.to_string()))
+                }
+                None => request,
+            }
+            .dispatch()
+            .status()
+        };
+
+        // reading needs no CSRF token
+        let response = client.get("/user/tokens").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // changing the state does, a missing or mismatched one being rejected
+        assert_eq!(add_token(None), Status::Forbidden);
+        assert_eq!(add_token(Some("mismatched")), Status::Forbidden);
+        assert_eq!(add_token(Some("")), Status::Forbidden);
+        assert_eq!(add_token(Some(&csrf_token)), Status::Created);
+    }
+
+    #[test]
+    fn require_the_csrf_token_to_refresh() {
+        let client = client(settings());
+        let csrf_token = login(&client, "user", true)
+            .into_json::<ApiKey>()
+            .unwrap()
+            .csrf_token
+            .unwrap();
+
+        let response = client.post("/user/refresh?cookie=true").dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client
+            .post("/user/refresh?cookie=true")
+            .header(Header::new("X-CSRF-Token", "mismatched"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        // the refresh token is left unused by the rejected requests
+        let response = client
+            .post("/user/refresh?cookie=true")
+            .header(Header::new("X-CSRF-Token", csrf_token))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+}
@@ -0,0 +1,29 @@
+use crate::server::guards::jwt::RenewedToken;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Request, Response,
+};
+
+/// Response header carrying a renewed access token
+const RENEWED_TOKEN_HEADER: &str = "X-Renewed-Token";
+
+/// Hand the tokens renewed by the bearer guard back to the clients
+pub struct TokenRenewal;
+
+#[rocket::async_trait]
+impl Fairing for TokenRenewal {
+    fn info(&self) -> Info {
+        Info {
+            name: "Sliding token expiration",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if let RenewedToken(Some(token)) = request.local_cache(|| RenewedToken(None)) {
+            response.set_header(Header::new(RENEWED_TOKEN_HEADER, token.clone()));
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::error::Error;
+
+use jwt_simple::reexports::ct_codecs::{Base64, Decoder};
+use rocket::{
+    http::Status,
+    outcome::Outcome,
+    request::{self, FromRequest, Request},
+};
+
+/// Maximum stored length of the `User-Agent` header
+const MAX_USER_AGENT_LEN: usize = 255;
+
+/// Information about the client a request comes from
+#[derive(OpenApiFromRequest)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LEN).collect()),
+            ip_address: request.client_ip().map(|ip| ip.to_string()),
+        })
+    }
+}
+
+/// Client id & secret sent with HTTP Basic authentication
+#[derive(OpenApiFromRequest)]
+pub struct ClientCredentials {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientCredentials {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Basic "))
+            .and_then(|credentials| Base64::decode_to_vec(credentials.trim(), None).ok())
+            .and_then(|credentials| String::from_utf8(credentials).ok())
+            .and_then(|credentials| {
+                credentials
+                    .split_once(':')
+                    .map(|(client_id, client_secret)| ClientCredentials {
+                        client_id: client_id.to_string(),
+                        client_secret: client_secret.to_string(),
+                    })
+            }) {
+            Some(credentials) => Outcome::Success(credentials),
+            None => Outcome::Error((Status::Unauthorized, Error::UnauthenticatedUser)),
+        }
+    }
+}
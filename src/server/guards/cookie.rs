@@ -0,0 +1,30 @@
+use crate::{error::Error, server::config::SessionCookieConfig};
+
+use rocket::{
+    http::Status,
+    outcome::Outcome,
+    request::{self, FromRequest, Request},
+};
+
+/// Refresh token of the refresh cookie, set when authenticating with `?cookie=true`
+#[derive(OpenApiFromRequest)]
+pub struct RefreshCookie(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RefreshCookie {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request
+            .rocket()
+            .state::<Option<SessionCookieConfig>>()
+            .and_then(Option::as_ref)
+        {
+            Some(session_cookie) => match session_cookie.refresh_token(request) {
+                Ok(refresh_token) => Outcome::Success(RefreshCookie(refresh_token)),
+                Err(e) => Outcome::Error((Status::Forbidden, e)),
+            },
+            None => Outcome::Success(RefreshCookie(None)),
+        }
+    }
+}
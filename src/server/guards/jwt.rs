@@ -5,8 +5,120 @@ use rocket::{
     State,
 };
 
-use crate::{backends::Backend, db::model::ApiKey, error::Error, secure::tokenizer::Tokenizer};
+use crate::{
+    backends::Backend,
+    db::model::ApiKey,
+    error::Error,
+    secure::tokenizer::{roles, TokenClaims, Tokenizer, PERSONAL_ACCESS_TOKEN_PREFIX},
+    server::config::{SessionCookieConfig, VerificationMode},
+};
+
+/// Verify a bearer token and make sure it was not revoked since
+pub(crate) fn validate_token(
+    token: &str,
+    tokenizer: &Tokenizer,
+    backend: &Backend,
+    verification: VerificationMode,
+) -> Result<TokenClaims, Error> {
+    // personal access tokens are opaque, and always looked up
+    if token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX) {
+        let (personal_access_token, user) = backend
+            .find_personal_access_token(&tokenizer.digest(token))
+            .map_err(|_| Error::UnauthenticatedUser)?;
+
+        return Ok(TokenClaims {
+            subject: user.username,
+            token_id: personal_access_token.id,
+            issued_at: personal_access_token.created_at as u64,
+            expires_at: personal_access_token.expires_at.map(|expires_at| expires_at as u64),
+            is_admin: user.is_admin,
+            roles: roles(user.is_admin),
+            scopes: Some(
+                personal_access_token
+                    .scopes
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect(),
+            ),
+            ..Default::default()
+        });
+    }
+
+    let mut claims = tokenizer.verify(token)?;
+
+    if backend
+        .is_token_revoked(&claims.token_id)
+        .map_err(|_| Error::InternalError)?
+    {
+        return Err(Error::UnauthenticatedUser);
+    }
+
+    // the tokens of the trusted issuer are mapped to a user, or an ephemeral principal
+    if let (true, Some(trusted_issuer)) = (claims.external, tokenizer.trusted_issuer()) {
+        let user = backend.get_user(&claims.subject);
+        return trusted_issuer.map_principal(claims, user);
+    }
+
+    match (verification, &claims.client_id) {
+        // the client must still be registered
+        (VerificationMode::Strict, Some(client_id)) => {
+            backend
+                .get_client(client_id)
+                .map_err(|_| Error::UnauthenticatedUser)?;
+        }
+        // the token must still belong to a live session of the user
+        (VerificationMode::Strict, None) => {
+            claims.is_admin = backend
+                .find_user_by_token(&tokenizer.digest(token))
+                .map_err(|_| Error::UnauthenticatedUser)?
+                .is_admin;
+        }
+        (VerificationMode::RevocationList, _) => {}
+    }
+
+    Ok(claims)
+}
 
+/// Token renewed by the bearer guard, sent back in a response header
+pub(crate) struct RenewedToken(pub Option<String>);
+
+/// Sliding expiration: renew a token getting old, for the response to carry it
+fn renew_token(
+    request: &Request<'_>,
+    token: &str,
+    claims: &TokenClaims,
+    tokenizer: &Tokenizer,
+    backend: &Backend,
+    verification: VerificationMode,
+) {
+    // a token being logged out is not renewed, or the renewed one would outlive the logout
+    if request.route().and_then(|route| route.name.as_deref()) == Some("logout") {
+        return;
+    }
+
+    let renewed = tokenizer.renew(claims).ok().flatten().filter(|renewed| {
+        // the session must follow, or the renewed token would be rejected
+        match (verification, &claims.session_id) {
+            (VerificationMode::Strict, Some(session_id)) => backend
+                .renew_session_token(
+                    session_id,
+                    &tokenizer.digest(token),
+                    &tokenizer.digest(renewed),
+                )
+                .is_ok(),
+            (VerificationMode::Strict, None) => false,
+            (VerificationMode::RevocationList, _) => true,
+        }
+    });
+
+    if renewed.is_some() {
+        request.local_cache(|| RenewedToken(renewed));
+    }
+}
+
+/// Authenticate the bearer of a token, without authorizing it: the guard lets every user
+/// through, so that routes like `/user/logout` are not limited to admins, and each handler
+/// checks the rights it needs with `ApiKey::require_admin` & `ApiKey::require_scope`
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for ApiKey {
     type Error = Error;
@@ -22,29 +134,63 @@ impl<'r> FromRequest<'r> for ApiKey {
             .await
             .map_error(|_| (Status::InternalServerError, Error::InternalError)));
 
-        match request
+        let verification = try_outcome!(request
+            .guard::<&State<VerificationMode>>()
+            .await
+            .map_error(|_| (Status::InternalServerError, Error::InternalError)));
+
+        // a bearer token first, the session cookie otherwise
+        let bearer = request
             .headers()
             .get_one("Authorization")
             .map(|header| header.split("Bearer").collect::<Vec<_>>())
-            .ok_or(Error::UnauthenticatedUser)
-            .and_then(|bearer| {
-                let token = bearer
+            .map(|bearer| {
+                bearer
                     .as_slice()
                     .get(1)
                     .map(|token| token.trim())
-                    .unwrap_or_default();
+                    .unwrap_or_default()
+                    .to_string()
+            });
+        let session_cookie = request
+            .rocket()
+            .state::<Option<SessionCookieConfig>>()
+            .and_then(Option::as_ref);
+        let from_cookie = bearer.is_none();
+        let token = match (bearer, session_cookie) {
+            (Some(token), _) => Ok(token),
+            (None, Some(session_cookie)) => session_cookie
+                .session_token(request)
+                .and_then(|token| token.ok_or(Error::UnauthenticatedUser)),
+            (None, None) => Err(Error::UnauthenticatedUser),
+        };
 
-                tokenizer.verify(token).map(|_| token.to_string())
+        match token.and_then(|token| {
+            validate_token(&token, tokenizer, backend, *verification.inner()).map(|claims| {
+                renew_token(request, &token, &claims, tokenizer, backend, *verification.inner());
+
+                if let (true, Some(session_cookie), RenewedToken(Some(renewed))) = (
+                    from_cookie,
+                    session_cookie,
+                    request.local_cache(|| RenewedToken(None)),
+                ) {
+                    session_cookie.renew_session(request.cookies(), renewed);
+                }
+
+                ApiKey {
+                    token,
+                    refresh_token: None,
+                    csrf_token: None,
+                    claims,
+                }
             })
-            .and_then(|token| match backend.find_user_by_token(&token) {
-                Ok(user) if user.is_admin => Ok(ApiKey { token }),
-                Ok(_) => Err(Error::ForbiddenAccess),
-                Err(_) => Err(Error::UnauthenticatedUser),
-            }) {
+        }) {
             Ok(api_key) => Outcome::Success(api_key),
+            Err(Error::ForbiddenAccess) => {
+                Outcome::Error((Status::Forbidden, Error::ForbiddenAccess))
+            }
             Err(e) => Outcome::Error((Status::Unauthorized, e)),
         }
-
     }
 }
 
@@ -1 +1,3 @@
+pub(crate) mod client;
+pub(crate) mod cookie;
 pub(crate) mod jwt;
@@ -1,4 +1,9 @@
-use crate::{backends::Backend, error::Error, secure::tokenizer::Tokenizer, Result};
+use crate::{
+    backends::Backend,
+    error::Error,
+    secure::{password::PasswordHasher, password_policy::PasswordPolicy, tokenizer::Tokenizer},
+    Result,
+};
 use clap::Parser;
 use rocket::{data::Limits, Build, Config, Rocket};
 use rocket_okapi::{
@@ -11,12 +16,20 @@ use std::path::Path;
 mod catchers;
 /// All the Routes/endpoints
 mod controllers;
+/// Session cookies delivering the tokens to browsers
+mod cookies;
+/// Response fairings
+mod fairings;
 /// All required Guards
 pub(crate) mod guards;
 
 /// Server & App Configurations
 mod config;
-use self::config::Settings;
+use self::config::{PublicUrl, Settings};
+
+/// Test server, on a private in-memory database
+#[cfg(all(test, feature = "sqlite"))]
+pub(crate) mod testing;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
@@ -42,7 +55,7 @@ fn parse_settings_from_cli() -> Result<Settings> {
         if Path::new(cfg_file).exists() {
             // load settings from the config file or return error
             // if error in loading the given config file
-            Settings::from_file(&cfg_file)
+            Settings::from_file(cfg_file)
         } else {
             // config file does not exist, quit app
             Err(Error::ConfigFileNotFound)
@@ -50,19 +63,78 @@ fn parse_settings_from_cli() -> Result<Settings> {
     }
 }
 
+/// Hasher and policy of the user passwords, as configured in the given file (the defaults if none)
+pub fn password_settings(cfg_file: Option<&str>) -> Result<(PasswordHasher, PasswordPolicy)> {
+    let settings = match cfg_file {
+        Some(cfg_file) if Path::new(cfg_file).exists() => Settings::from_file(cfg_file)?,
+        Some(_) => return Err(Error::ConfigFileNotFound),
+        None => Settings::default(),
+    };
+
+    Ok((
+        settings.server.password_hasher()?,
+        settings.server.password_policy()?,
+    ))
+}
+
 /// Initialise the Rocket Server app
 pub async fn init_server() -> Result<Rocket<Build>> {
-    let settings = parse_settings_from_cli()?;
+    build_server(parse_settings_from_cli()?)
+}
 
+/// Configure the Rocket Server app with the given settings
+fn build_server(settings: Settings) -> Result<Rocket<Build>> {
     let app_settings = settings.app.unwrap_or_default();
 
     if app_settings.db_url.is_empty() {
         return Err(Error::EmptyDBUrl)
     }
 
+    settings.server.check_secret_key()?;
+    settings.server.check_public_url()?;
+
     let token_expires = parse_duration::parse(&settings.server.jwt_token_expiry)?;
+    let refresh_token_expires = parse_duration::parse(&settings.server.jwt_refresh_token_expiry)?;
     let jwt_secret = settings.server.secret_key.to_owned();
 
+    let tokenizer = settings.server.jwt_retired_keys()?.into_iter().fold(
+        Tokenizer::new(
+            token_expires,
+            refresh_token_expires,
+            settings.server.jwt_validation()?,
+            &settings.server.token_digest_secret()?,
+            settings.server.jwt_signing_key()?,
+            settings.server.jwt_verifying_key()?,
+        )?,
+        |tokenizer, (key, expires_at)| tokenizer.with_retired_key(key, expires_at),
+    );
+    let tokenizer = match settings.server.jwt_encryption_key()? {
+        Some(key) => tokenizer.with_encryption_key(key),
+        None => tokenizer,
+    };
+    let tokenizer = match settings.server.paseto_key()? {
+        Some(key) => tokenizer.with_paseto_key(key),
+        None => tokenizer,
+    };
+    let tokenizer = match settings.server.trusted_issuer()? {
+        Some(trusted_issuer) => tokenizer.with_trusted_issuer(trusted_issuer),
+        None => tokenizer,
+    };
+    let tokenizer = tokenizer.with_impersonation_token_expiration(parse_duration::parse(
+        &settings.server.jwt_impersonation_token_expiry,
+    )?);
+    let tokenizer = tokenizer.with_action_token_expiration(parse_duration::parse(
+        &settings.server.jwt_action_token_expiry,
+    )?);
+    let sliding_expiration = settings.server.jwt_sliding_expiration()?;
+    let tokenizer = match sliding_expiration.clone() {
+        Some(sliding_expiration) => tokenizer.with_sliding_expiration(sliding_expiration),
+        None => tokenizer,
+    };
+
+    let password_hasher = settings.server.password_hasher()?;
+    let password_policy = settings.server.password_policy()?;
+
     let limits = Limits::new()
         .limit("forms", settings.server.forms_limit.into())
         .limit("json", settings.server.json_limit.into());
@@ -71,7 +143,7 @@ pub async fn init_server() -> Result<Rocket<Build>> {
         .merge(("address", settings.server.host.to_string()))
         .merge(("port", settings.server.port as u16))
         .merge(("limits", limits))
-        .merge(("secret_key", (settings.server.secret_key.as_str())))
+        .merge(("secret_key", settings.server.cookie_secret_key()?))
         .merge(("keep_alive", settings.server.keep_alive as u32));
 
     // Configure SSL status for the api server
@@ -112,15 +184,53 @@ pub async fn init_server() -> Result<Rocket<Build>> {
         ],
     );
 
+    // Send the renewed tokens back
+    let app = match sliding_expiration {
+        Some(_) => app.attach(fairings::TokenRenewal),
+        None => app,
+    };
+
     // Add the user routes
     let app = app.mount(
         "/user",
         openapi_get_routes![
             controllers::user::authenticate_user,
+            controllers::user::refresh_token,
+            controllers::user::logout,
             controllers::user::add_user,
             controllers::user::delete_user,
             controllers::user::change_user_password,
             controllers::user::get_all_users,
+            controllers::user::impersonate_user,
+            controllers::user::add_action_token,
+            controllers::user::reset_user_password,
+            controllers::user::verify_user_email,
+            controllers::user::add_personal_access_token,
+            controllers::user::get_personal_access_tokens,
+            controllers::user::delete_personal_access_token,
+        ],
+    );
+
+    // Add the oauth routes
+    let app = app.mount(
+        "/oauth",
+        openapi_get_routes![
+            controllers::oauth::token,
+            controllers::oauth::introspect,
+            controllers::oauth::userinfo,
+            controllers::oauth::post_userinfo,
+            controllers::oauth::add_client,
+            controllers::oauth::get_all_clients,
+            controllers::oauth::delete_client,
+        ],
+    );
+
+    // Add the well-known routes
+    let app = app.mount(
+        "/.well-known",
+        rocket::routes![
+            controllers::well_known::jwks,
+            controllers::well_known::openid_configuration
         ],
     );
 
@@ -137,10 +247,19 @@ pub async fn init_server() -> Result<Rocket<Build>> {
         // Add server settings to state just in case
         .manage(jwt_secret)
         // add tokenizer info to the state
-        .manage(Tokenizer::new(
-            token_expires,
-            Some(&settings.server.secret_key),
-        ))
+        .manage(tokenizer)
+        // add the password hasher to the state
+        .manage(password_hasher)
+        // add the password policy to the state
+        .manage(password_policy)
+        // add the token verification mode to the state
+        .manage(settings.server.jwt_verification)
+        // add the clients allowed to introspect tokens to the state
+        .manage(settings.server.introspection_clients)
+        // add the delivery of the tokens in cookies, if enabled, to the state
+        .manage(settings.server.session_cookie)
+        // add the public base URL of the server to the state
+        .manage(PublicUrl(settings.server.public_url))
         // add the Backend to the state
         .manage(Backend::new(&app_settings.db_url)?);
 
@@ -0,0 +1,102 @@
+//! Test server, on a private in-memory database
+use crate::{
+    backends::Backend,
+    db::model::{ApiKey, User},
+    secure::{keys::Algorithm, password::PasswordHasher, tokenizer::random_id},
+    server::{
+        build_server,
+        config::{App, PasswordHashingConfig, ServerConfig, SessionCookieConfig, Settings},
+    },
+    testing::{database_url, migrate, user, RSA_PRIVATE_KEY, SECRET_KEY},
+};
+
+use rocket::{
+    http::{ContentType, Status},
+    local::blocking::{Client, LocalResponse},
+    serde::json::{json, serde_json},
+};
+
+/// Password of the users of the test server
+pub(crate) const PASSWORD: &str = "Tr0ub4dor&3";
+
+/// Settings of a test server, able to deliver the tokens in cookies
+pub(crate) fn settings() -> Settings {
+    Settings {
+        server: ServerConfig {
+            secret_key: SECRET_KEY.to_string(),
+            session_cookie: Some(SessionCookieConfig {
+                // the test client does not speak HTTPS
+                secure: false,
+                ..serde_json::from_str("{}").unwrap()
+            }),
+            // low costs, for the tests to run fast
+            password_hashing: PasswordHashingConfig {
+                memory_cost: 1024,
+                time_cost: 1,
+                parallelism: 1,
+            },
+            ..Default::default()
+        },
+        ssl: None,
+        app: Some(App {
+            db_url: database_url(),
+        }),
+    }
+}
+
+/// Settings of a test server signing its tokens with RS256, able to issue ID tokens
+pub(crate) fn rs256_settings() -> Settings {
+    // shared by the test servers, and renamed into place for them to never read it half written
+    let key_file = std::env::temp_dir().join("rocketjwt-test-rs256.pem");
+    let written = key_file.with_extension(random_id(8));
+    std::fs::write(&written, RSA_PRIVATE_KEY).unwrap();
+    std::fs::rename(&written, &key_file).unwrap();
+
+    let mut settings = settings();
+    settings.server.jwt_algorithm = Algorithm::RS256;
+    settings.server.jwt_private_key_file = Some(key_file.to_string_lossy().into_owned());
+    settings
+}
+
+/// Client of a test server, tracking its cookies, with the `admin` admin and the `user` user
+pub(crate) fn client(settings: Settings) -> Client {
+    let database_url = settings.app.clone().unwrap_or_default().db_url;
+    let client = Client::tracked(build_server(settings).unwrap()).unwrap();
+    migrate(&database_url);
+
+    let hasher = client.rocket().state::<PasswordHasher>().unwrap();
+    for (username, is_admin) in [("admin", true), ("user", false)] {
+        backend(&client)
+            .add_user(User {
+                username: username.to_string(),
+                email: format!("{}@example.com", username),
+                password: hasher.hash(PASSWORD).unwrap(),
+                ..user(is_admin)
+            })
+            .unwrap();
+    }
+
+    client
+}
+
+/// Backend of a test server
+pub(crate) fn backend(client: &Client) -> &Backend {
+    client.rocket().state::<Backend>().unwrap()
+}
+
+/// Log a user in, the tokens being delivered in the session cookies or in the response
+pub(crate) fn login<'c>(client: &'c Client, username: &str, cookie: bool) -> LocalResponse<'c> {
+    let response = client
+        .post(format!("/user/auth?cookie={}", cookie))
+        .header(ContentType::JSON)
+        .body(json!({ "username": username, "password": PASSWORD }).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    response
+}
+
+/// Tokens of a logged in user, delivered in the response
+pub(crate) fn tokens(client: &Client, username: &str) -> ApiKey {
+    login(client, username, false).into_json().unwrap()
+}